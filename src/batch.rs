@@ -0,0 +1,224 @@
+use std::any::Any;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use error::{Error, Result};
+use scoped_threadpool::Pool;
+use simulation::Options;
+
+const SUPPORTED_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "gif", "bmp", "ico", "tif", "tiff", "webp", "pbm", "pgm", "ppm", "pnm",
+];
+
+/// outcome of a batch run, one entry per input image
+#[derive(Debug, Default)]
+pub struct Report {
+    pub processed: Vec<PathBuf>,
+    pub skipped: Vec<PathBuf>,
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+impl Report {
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+/**
+ * @brief      runs the simulation for every supported image in `input_dir`
+ *
+ * The results are written to `out_dir` using the input file name, extension
+ * included so `a.png` and `a.jpg` don't collide, followed by the extension of
+ * `Options::output`. Inputs whose outputs are newer than the input are
+ * skipped unless `force` is set; only the modification times are compared,
+ * outputs of a run with other options count as up to date. `Options::threads`
+ * is shared between the images that are processed at the same time, a
+ * failing or panicking image does not stop the batch.
+ */
+pub fn run(input_dir: &Path, out_dir: &Path, options: &Options, force: bool) -> Result<Report> {
+    options.validate()?;
+    if !input_dir.is_dir() {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{}: not a directory", input_dir.display()),
+        )));
+    }
+    fs::create_dir_all(out_dir)?;
+
+    let mut inputs: Vec<PathBuf> = Vec::new();
+    for entry in fs::read_dir(input_dir)? {
+        let path = entry?.path();
+        if path.is_file() && is_supported(&path) {
+            inputs.push(path);
+        }
+    }
+    inputs.sort();
+    info!(
+        "batch: {} images found in {}",
+        inputs.len(),
+        input_dir.display()
+    );
+
+    let report = Mutex::new(Report::default());
+    if inputs.is_empty() {
+        return Ok(report.into_inner().unwrap());
+    }
+
+    let jobs = options.threads.min(inputs.len() as u32);
+    let extension = options
+        .output
+        .extension()
+        .map(|ext| ext.to_owned())
+        .unwrap_or_else(|| "png".into());
+
+    // every job keeps its share of the threads and takes the next image
+    // once it is done with one
+    let queue = Mutex::new(inputs.iter());
+    let mut pool = Pool::new(jobs);
+    pool.scoped(|scoped| {
+        for threads in job_threads(options.threads, jobs) {
+            let (queue, report, extension) = (&queue, &report, &extension);
+            scoped.execute(move || loop {
+                let input = match queue.lock().unwrap().next() {
+                    Some(input) => input,
+                    None => break,
+                };
+                let output = output_path(input, out_dir, extension);
+                if !force && is_up_to_date(input, &output) {
+                    info!("batch: {} is up to date", output.display());
+                    report.lock().unwrap().skipped.push(input.clone());
+                    continue;
+                }
+
+                let mut job_options = options.clone();
+                job_options.threads = threads;
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    ::run(input, Some(job_options)).and_then(|model| model.export(&output))
+                }));
+                let failure = match result {
+                    Ok(Ok(_)) => None,
+                    Ok(Err(e)) => Some(e.to_string()),
+                    Err(payload) => Some(panic_message(&*payload)),
+                };
+                match failure {
+                    None => report.lock().unwrap().processed.push(input.clone()),
+                    Some(msg) => {
+                        error!("batch: {} failed: {}", input.display(), msg);
+                        report.lock().unwrap().failed.push((input.clone(), msg));
+                    }
+                }
+            });
+        }
+    });
+
+    Ok(report.into_inner().unwrap())
+}
+
+/// splits `threads` between `jobs`, the first `threads % jobs` jobs get one
+/// of the leftover threads
+fn job_threads(threads: u32, jobs: u32) -> Vec<u32> {
+    (0..jobs)
+        .map(|job| (threads / jobs + u32::from(job < threads % jobs)).max(1))
+        .collect()
+}
+
+/// `out_dir/<input file name>.<extension>`, the shape file is named alike
+fn output_path(input: &Path, out_dir: &Path, extension: &OsString) -> PathBuf {
+    // every supported file has a file name
+    let mut name = input.file_name().unwrap().to_owned();
+    name.push(".");
+    name.push(extension);
+    out_dir.join(name)
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    let msg = payload
+        .downcast_ref::<&str>()
+        .map(|msg| msg.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown error".to_owned());
+    format!("panicked: {}", msg)
+}
+
+fn is_supported(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| SUPPORTED_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+/**
+ * @brief      an output is up to date when it and its shape file are newer
+ *             than the input
+ */
+fn is_up_to_date(input: &Path, output: &Path) -> bool {
+    let modified = |p: &Path| fs::metadata(p).and_then(|m| m.modified()).ok();
+    match (
+        modified(input),
+        modified(output),
+        modified(&output.with_extension("shapes")),
+    ) {
+        (Some(input), Some(output), Some(shapes)) => output >= input && shapes >= input,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::File;
+
+    #[test]
+    fn should_recognize_supported_images() {
+        assert!(is_supported(Path::new("photo.JPG")));
+        assert!(is_supported(Path::new("lena_std.tif")));
+        assert!(!is_supported(Path::new("notes.txt")));
+        assert!(!is_supported(Path::new("README")));
+    }
+
+    #[test]
+    fn should_keep_input_extensions_apart() {
+        let png = OsString::from("png");
+        let out = Path::new("results");
+        let a = output_path(Path::new("photos/a.png"), out, &png);
+        let b = output_path(Path::new("photos/a.jpg"), out, &png);
+        assert_eq!(a, Path::new("results/a.png.png"));
+        assert_eq!(b, Path::new("results/a.jpg.png"));
+        assert_ne!(a.with_extension("shapes"), b.with_extension("shapes"));
+    }
+
+    #[test]
+    fn should_hand_out_every_thread() {
+        assert_eq!(job_threads(7, 4), vec![2, 2, 2, 1]);
+        assert_eq!(job_threads(8, 4), vec![2, 2, 2, 2]);
+        assert_eq!(job_threads(3, 3), vec![1, 1, 1]);
+    }
+
+    #[test]
+    fn should_describe_panics() {
+        let payload = panic::catch_unwind(|| panic!("out of {}", "range")).unwrap_err();
+        assert_eq!(panic_message(&*payload), "panicked: out of range");
+        let payload = panic::catch_unwind(|| panic!("static")).unwrap_err();
+        assert_eq!(panic_message(&*payload), "panicked: static");
+    }
+
+    #[test]
+    fn should_skip_up_to_date_outputs() {
+        let dir = env::temp_dir().join("geoshaper-batch-up-to-date");
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("input.png");
+        let output = dir.join("output.png");
+        File::create(&input).unwrap();
+        assert!(!is_up_to_date(&input, &output));
+
+        File::create(&output).unwrap();
+        File::create(output.with_extension("shapes")).unwrap();
+        assert!(is_up_to_date(&input, &output));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
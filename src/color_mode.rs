@@ -0,0 +1,240 @@
+use std::fmt;
+
+use image::{DynamicImage, GenericImage, Rgba, RgbaImage};
+use image_utils::parse_hex_color;
+
+/// default ink of `mono`
+const MONO_INK: &str = "#000000";
+/// default shadow and highlight colors of `duotone`
+const DUOTONE_INKS: &str = "#000000,#ffffff";
+
+/**
+ * How shape colors are chosen. Every mode but `Color` maps the target to the
+ * colors it can reproduce first, candidates are then scored against that
+ * image by the usual fitness functions.
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ColorMode {
+    /// the average color of the target below the shape
+    #[default]
+    Color,
+    /// the luminance of the target only
+    Grayscale,
+    /// a single ink on the background, the shapes vary in opacity
+    Mono(Rgba<u8>),
+    /// a blend between a shadow and a highlight color
+    Duotone(Rgba<u8>, Rgba<u8>),
+}
+
+impl ColorMode {
+    /**
+     * @brief      parses a mode and its ink colors
+     *
+     * @param      mode  color, grayscale, mono or duotone
+     * @param      inks  `#rrggbb` for mono, `#rrggbb,#rrggbb` for duotone;
+     *                   black, and black to white, by default
+     */
+    pub fn parse(mode: &str, inks: Option<&str>) -> Result<ColorMode, String> {
+        let colors = |default: &str, n: usize| -> Result<Vec<Rgba<u8>>, String> {
+            let inks = inks.unwrap_or(default);
+            let colors = inks
+                .split(',')
+                .map(|ink| {
+                    parse_hex_color(ink.trim()).ok_or_else(|| format!("invalid ink: {}", ink))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if colors.len() != n {
+                return Err(format!("{} needs {} ink colors, found {}", mode, n, inks));
+            }
+            Ok(colors)
+        };
+        match mode {
+            "color" | "grayscale" if inks.is_some() => {
+                Err(format!("{} does not take ink colors", mode))
+            }
+            "color" => Ok(ColorMode::Color),
+            "grayscale" => Ok(ColorMode::Grayscale),
+            "mono" => Ok(ColorMode::Mono(colors(MONO_INK, 1)?[0])),
+            "duotone" => {
+                let colors = colors(DUOTONE_INKS, 2)?;
+                Ok(ColorMode::Duotone(colors[0], colors[1]))
+            }
+            _ => Err(format!("unknown color mode: {}", mode)),
+        }
+    }
+
+    /// `background` mapped to the colors of this mode, the paper below the
+    /// ink for `Mono`
+    pub fn background(&self, background: Rgba<u8>) -> Rgba<u8> {
+        self.map(&background, &background)
+    }
+
+    /// the image the shapes are scored against
+    pub fn target(&self, target: &DynamicImage, background: Rgba<u8>) -> DynamicImage {
+        if *self == ColorMode::Color {
+            return target.clone();
+        }
+        let (width, height) = target.dimensions();
+        let img = RgbaImage::from_fn(width, height, |x, y| {
+            self.map(&target.get_pixel(x, y), &background)
+        });
+        DynamicImage::ImageRgba8(img)
+    }
+
+    /**
+     * @brief      the fill of a shape over an area of the mapped target
+     *
+     * @param      color       average color of the mapped target below the
+     *                         shape
+     * @param      alpha       opacity of the shapes, `Mono` derives its own
+     * @param      background  the paper of `Mono`
+     */
+    pub fn fill(&self, color: Rgba<u8>, alpha: u8, background: Rgba<u8>) -> Rgba<u8> {
+        let mut fill = match *self {
+            ColorMode::Mono(ink) => {
+                // as much ink as the target is darker than the paper
+                let coverage = project(&color, &background, &ink);
+                let mut fill = ink;
+                fill.data[3] = (coverage * 255.0).round() as u8;
+                return fill;
+            }
+            _ => color,
+        };
+        fill.data[3] = alpha;
+        fill
+    }
+
+    /// `color` in this mode, the alpha is kept
+    fn map(&self, color: &Rgba<u8>, background: &Rgba<u8>) -> Rgba<u8> {
+        let mut mapped = match *self {
+            ColorMode::Color => *color,
+            ColorMode::Grayscale => {
+                let luma = (0.299 * color.data[0] as f32
+                    + 0.587 * color.data[1] as f32
+                    + 0.114 * color.data[2] as f32)
+                    .round() as u8;
+                Rgba {
+                    data: [luma, luma, luma, 255],
+                }
+            }
+            ColorMode::Mono(ink) => {
+                let t = project(color, background, &ink);
+                blend(background, &ink, t)
+            }
+            ColorMode::Duotone(shadow, highlight) => {
+                let t = project(color, &shadow, &highlight);
+                blend(&shadow, &highlight, t)
+            }
+        };
+        mapped.data[3] = color.data[3];
+        mapped
+    }
+}
+
+/// position of `color` projected on the line from `from` to `to`, clamped to
+/// `[0, 1]`
+fn project(color: &Rgba<u8>, from: &Rgba<u8>, to: &Rgba<u8>) -> f32 {
+    let mut dot = 0.0;
+    let mut length = 0.0;
+    for c in 0..3 {
+        let d = to.data[c] as f32 - from.data[c] as f32;
+        dot += (color.data[c] as f32 - from.data[c] as f32) * d;
+        length += d * d;
+    }
+    if length > 0.0 {
+        (dot / length).clamp(0.0, 1.0)
+    } else {
+        0.0
+    }
+}
+
+fn blend(from: &Rgba<u8>, to: &Rgba<u8>, t: f32) -> Rgba<u8> {
+    let mut color = *from;
+    for c in 0..3 {
+        color.data[c] =
+            (from.data[c] as f32 + (to.data[c] as f32 - from.data[c] as f32) * t).round() as u8;
+    }
+    color
+}
+
+impl fmt::Display for ColorMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ColorMode::Color => write!(f, "color"),
+            ColorMode::Grayscale => write!(f, "grayscale"),
+            ColorMode::Mono(_) => write!(f, "mono"),
+            ColorMode::Duotone(..) => write!(f, "duotone"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgba(r: u8, g: u8, b: u8, a: u8) -> Rgba<u8> {
+        Rgba { data: [r, g, b, a] }
+    }
+
+    #[test]
+    fn should_parse_color_modes() {
+        assert_eq!(ColorMode::parse("color", None), Ok(ColorMode::Color));
+        assert_eq!(
+            ColorMode::parse("mono", None),
+            Ok(ColorMode::Mono(rgba(0, 0, 0, 255)))
+        );
+        assert_eq!(
+            ColorMode::parse("duotone", Some("#102030, #ffeedd")),
+            Ok(ColorMode::Duotone(
+                rgba(16, 32, 48, 255),
+                rgba(255, 238, 221, 255)
+            ))
+        );
+        assert!(ColorMode::parse("duotone", Some("#102030")).is_err());
+        assert!(ColorMode::parse("grayscale", Some("#102030")).is_err());
+        assert!(ColorMode::parse("sepia", None).is_err());
+    }
+
+    #[test]
+    fn should_map_to_luminance() {
+        let mode = ColorMode::Grayscale;
+        assert_eq!(mode.background(rgba(255, 0, 0, 255)), rgba(76, 76, 76, 255));
+        let fill = mode.fill(rgba(76, 76, 76, 255), 128, rgba(0, 0, 0, 255));
+        assert_eq!(fill, rgba(76, 76, 76, 128));
+    }
+
+    #[test]
+    fn should_vary_mono_opacity() {
+        let paper = rgba(255, 255, 255, 255);
+        let mode = ColorMode::Mono(rgba(0, 0, 128, 255));
+        let target = DynamicImage::ImageRgba8(RgbaImage::from_fn(2, 1, |x, _| {
+            if x == 0 {
+                rgba(255, 255, 255, 255)
+            } else {
+                rgba(0, 0, 128, 255)
+            }
+        }));
+        let mapped = mode.target(&target, paper);
+        assert_eq!(mapped.get_pixel(0, 0), paper);
+        assert_eq!(mapped.get_pixel(1, 0), rgba(0, 0, 128, 255));
+
+        assert_eq!(
+            mode.fill(rgba(0, 0, 128, 255), 179, paper),
+            rgba(0, 0, 128, 255)
+        );
+        assert_eq!(mode.fill(paper, 179, paper).data[3], 0);
+        let half = mode.fill(rgba(128, 128, 192, 255), 179, paper);
+        assert_eq!(half.data, [0, 0, 128, 127]);
+    }
+
+    #[test]
+    fn should_blend_duotone() {
+        let mode = ColorMode::Duotone(rgba(0, 0, 0, 255), rgba(255, 0, 0, 255));
+        // green has no red in it, so it is all shadow
+        assert_eq!(mode.background(rgba(0, 255, 0, 255)), rgba(0, 0, 0, 255));
+        assert_eq!(
+            mode.background(rgba(200, 100, 100, 64)),
+            rgba(200, 0, 0, 64)
+        );
+    }
+}
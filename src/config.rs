@@ -0,0 +1,101 @@
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+use error::{Error, Result};
+use serde_json;
+use simulation::Options;
+use toml;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Toml,
+    Json,
+}
+
+impl Format {
+    /**
+     * @brief      guesses the format from the file extension, defaults to toml
+     */
+    pub fn from_path(path: &Path) -> Format {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.to_lowercase() == "json" => Format::Json,
+            _ => Format::Toml,
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> ::std::result::Result<Format, String> {
+        match s {
+            "toml" => Ok(Format::Toml),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown config format: {}", s)),
+        }
+    }
+}
+
+/**
+ * @brief      reads simulation options from a toml or json file, missing
+ *             keys keep their default value
+ */
+pub fn load(path: &Path) -> Result<Options> {
+    let mut contents = String::new();
+    File::open(path)?.read_to_string(&mut contents)?;
+    let options = match Format::from_path(path) {
+        Format::Toml => toml::from_str(&contents).map_err(|e| config_error(path, &e))?,
+        Format::Json => serde_json::from_str(&contents).map_err(|e| config_error(path, &e))?,
+    };
+    Ok(options)
+}
+
+pub fn dump(options: &Options, format: Format) -> Result<String> {
+    let dumped = match format {
+        Format::Toml => toml::to_string(options).map_err(|e| e.to_string()),
+        Format::Json => serde_json::to_string_pretty(options)
+            .map(|json| json + "\n")
+            .map_err(|e| e.to_string()),
+    };
+    dumped.map_err(Error::InvalidOptions)
+}
+
+fn config_error<E: fmt::Display>(path: &Path, e: &E) -> Error {
+    Error::InvalidOptions(format!("{}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_fill_missing_keys_with_defaults() {
+        let options: Options = toml::from_str("shape = \"triangle\"\nmax_iter = 10").unwrap();
+        assert_eq!(options.shape, "triangle");
+        assert_eq!(options.max_iter, 10);
+        assert_eq!(options.num_genes, Options::default().num_genes);
+    }
+
+    #[test]
+    fn should_reject_unknown_keys() {
+        assert!(toml::from_str::<Options>("shapes = \"triangle\"").is_err());
+    }
+
+    #[test]
+    fn should_roundtrip_dump() {
+        let mut options = Options::default();
+        options.seed = Some(42);
+        for format in &[Format::Toml, Format::Json] {
+            let dumped = dump(&options, *format).unwrap();
+            let loaded: Options = match *format {
+                Format::Toml => toml::from_str(&dumped).unwrap(),
+                Format::Json => serde_json::from_str(&dumped).unwrap(),
+            };
+            assert_eq!(loaded.seed, Some(42));
+            assert_eq!(loaded.output, options.output);
+        }
+    }
+}
@@ -1,26 +1,127 @@
-use failure;
-
-pub type Result<T> = ::std::result::Result<T, failure::Error>;
-
-// Fix upstream usage of error-chain
-// TODO(robertgzr): maybe do a PR switching darwin_rs to failure as well?
-//
-// copypasta from:
-// https://github.com/rust-lang-nursery/failure/issues/109#issuecomment-350920299
-//
-pub trait ResultExt<T, E> {
-    fn sync(self) -> ::std::result::Result<T, failure::SyncFailure<E>>
-    where
-        Self: Sized,
-        E: ::std::error::Error + Send + 'static;
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use image::ImageError;
+use nsvg;
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// reading the target image from a file failed
+    Load(PathBuf, ImageError),
+    /// decoding an image buffer failed
+    Decode(ImageError),
+    /// nsvg could not parse or rasterize a generated svg
+    Rasterize(nsvg::Error),
+    /// an option is out of range or a config file could not be parsed
+    InvalidOptions(String),
+    /// a shape file could not be parsed
+    InvalidShapeFile(String),
+    /// a palette file could not be parsed
+    InvalidPalette(String),
+    /// a stamp file holds no single, visible svg path
+    InvalidStamp(String),
+    /// two images, or an image and a model, differ in size
+    SizeMismatch {
+        expected: (u32, u32),
+        found: (u32, u32),
+    },
+    /// reading input or writing output failed
+    Io(io::Error),
+    /// none of the mutations of a generation could be scored
+    NoWinner,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Load(ref path, ref e) => write!(f, "{}: {}", path.display(), e),
+            Error::Decode(ref e) => write!(f, "decoding image failed: {}", e),
+            Error::Rasterize(ref e) => write!(f, "rasterizing svg failed: {}", e),
+            Error::InvalidOptions(ref msg) => write!(f, "invalid options: {}", msg),
+            Error::InvalidShapeFile(ref msg) => write!(f, "invalid shape file: {}", msg),
+            Error::InvalidPalette(ref msg) => write!(f, "invalid palette: {}", msg),
+            Error::InvalidStamp(ref msg) => write!(f, "invalid stamp: {}", msg),
+            Error::SizeMismatch { expected, found } => write!(
+                f,
+                "size mismatch: expected {}x{}, found {}x{}",
+                expected.0, expected.1, found.0, found.1
+            ),
+            Error::Io(ref e) => write!(f, "{}", e),
+            Error::NoWinner => write!(
+                f,
+                "Unexpected end of mutation list, unable to find winner gene"
+            ),
+        }
+    }
 }
 
-impl<T, E> ResultExt<T, E> for ::std::result::Result<T, E> {
-    fn sync(self) -> ::std::result::Result<T, failure::SyncFailure<E>>
-    where
-        Self: Sized,
-        E: ::std::error::Error + Send + 'static,
-    {
-        self.map_err(failure::SyncFailure::new)
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Load(..) => "loading image failed",
+            Error::Decode(_) => "decoding image failed",
+            Error::Rasterize(_) => "rasterizing svg failed",
+            Error::InvalidOptions(_) => "invalid options",
+            Error::InvalidShapeFile(_) => "invalid shape file",
+            Error::InvalidPalette(_) => "invalid palette",
+            Error::InvalidStamp(_) => "invalid stamp",
+            Error::SizeMismatch { .. } => "size mismatch",
+            Error::Io(_) => "i/o error",
+            Error::NoWinner => "no winner gene found",
+        }
+    }
+
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Load(_, ref e) | Error::Decode(ref e) => Some(e),
+            Error::Rasterize(ref e) => Some(e),
+            Error::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ImageError> for Error {
+    fn from(e: ImageError) -> Error {
+        Error::Decode(e)
+    }
+}
+
+impl From<nsvg::Error> for Error {
+    fn from(e: nsvg::Error) -> Error {
+        Error::Rasterize(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as StdError;
+
+    #[test]
+    fn should_describe_size_mismatch() {
+        let e = Error::SizeMismatch {
+            expected: (64, 32),
+            found: (32, 32),
+        };
+        assert_eq!(e.to_string(), "size mismatch: expected 64x32, found 32x32");
+        assert!(e.source().is_none());
+    }
+
+    #[test]
+    fn should_keep_io_source() {
+        let e: Error = io::Error::new(io::ErrorKind::NotFound, "missing").into();
+        assert_eq!(e.to_string(), "missing");
+        assert!(e.source().is_some());
     }
 }
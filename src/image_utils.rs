@@ -3,7 +3,9 @@ use shape::Point;
 use std::fmt;
 use std::path::Path;
 use std::sync::Arc;
+use weights::{total_weight, weight_at, Weights};
 
+/// the rgb channels for an svg `fill`, the alpha goes into `fill-opacity`
 pub fn rgba_to_str<T>(color: &Rgba<T>) -> String
 where
     T: fmt::Display + image::Primitive,
@@ -17,21 +19,37 @@ pub fn load_image(p: &Path) -> ImageResult<DynamicImage> {
     image::open(p)
 }
 
+pub fn load_image_from_memory(buffer: &[u8]) -> ImageResult<DynamicImage> {
+    image::load_from_memory(buffer)
+}
+
+/// mean color of the area weighted by the alpha of every pixel
 pub fn get_average_color_from_area(img: Arc<DynamicImage>, bounds: [Point; 2]) -> Rgba<u8> {
     check_bounds(&bounds);
-    let mut r_sum: u32 = 0;
-    let mut g_sum: u32 = 0;
-    let mut b_sum: u32 = 0;
-    let mut count: u32 = 0;
+    let mut r_sum: u64 = 0;
+    let mut g_sum: u64 = 0;
+    let mut b_sum: u64 = 0;
+    let mut count: u64 = 0;
     for x in bounds[0].x as u32..bounds[1].x as u32 {
         for y in bounds[0].y as u32..bounds[1].y as u32 {
             let pixel = img.get_pixel(x, y);
-            r_sum += pixel.data[0] as u32;
-            g_sum += pixel.data[1] as u32;
-            b_sum += pixel.data[2] as u32;
-            count += 1;
+            let alpha = pixel.data[3] as u64;
+            r_sum += pixel.data[0] as u64 * alpha;
+            g_sum += pixel.data[1] as u64 * alpha;
+            b_sum += pixel.data[2] as u64 * alpha;
+            count += alpha;
         }
     }
+    if count == 0 {
+        // the area is thinner than a pixel or fully transparent, use the
+        // closest pixel
+        let (width, height) = img.dimensions();
+        let x = (bounds[0].x as u32).min(width - 1);
+        let y = (bounds[0].y as u32).min(height - 1);
+        let mut pixel = img.get_pixel(x, y);
+        pixel.data[3] = 255;
+        return pixel;
+    }
     let r_avg = r_sum / count;
     let g_avg = g_sum / count;
     let b_avg = b_sum / count;
@@ -41,16 +59,71 @@ pub fn get_average_color_from_area(img: Arc<DynamicImage>, bounds: [Point; 2]) -
     }
 }
 
+/// mean color along the closed outline through `points`, sampled once per
+/// pixel of length and weighted by alpha; samples off the image are skipped,
+/// black when there are none on it
+pub fn get_average_color_along(img: Arc<DynamicImage>, points: &[Point]) -> Rgba<u8> {
+    let (width, height) = img.dimensions();
+    let (range_x, range_y) = (width as f32, height as f32);
+    let mut sums: [u64; 3] = [0; 3];
+    let mut count: u64 = 0;
+    let mut closest = None;
+    for (i, from) in points.iter().enumerate() {
+        let to = &points[(i + 1) % points.len()];
+        let length = ((to.x - from.x).powi(2) + (to.y - from.y).powi(2)).sqrt();
+        let steps = length.ceil().max(1.0) as u32;
+        for step in 0..steps {
+            let t = step as f32 / steps as f32;
+            let (x, y) = (from.x + (to.x - from.x) * t, from.y + (to.y - from.y) * t);
+            if x < 0.0 || y < 0.0 || x >= range_x || y >= range_y {
+                continue;
+            }
+            let pixel = img.get_pixel(x as u32, y as u32);
+            closest = closest.or(Some(pixel));
+            let alpha = pixel.data[3] as u64;
+            for (sum, &value) in sums.iter_mut().zip(&pixel.data[..3]) {
+                *sum += value as u64 * alpha;
+            }
+            count += alpha;
+        }
+    }
+    match closest {
+        Some(mut pixel) if count == 0 => {
+            pixel.data[3] = 255;
+            pixel
+        }
+        None => Rgba {
+            data: [0, 0, 0, 255],
+        },
+        Some(_) => Rgba {
+            data: [
+                (sums[0] / count) as u8,
+                (sums[1] / count) as u8,
+                (sums[2] / count) as u8,
+                255,
+            ],
+        },
+    }
+}
+
+/// mean color of the image weighted by the alpha of every pixel, black when
+/// nothing is visible
 pub fn get_average_color(img: Arc<DynamicImage>) -> Rgba<u8> {
-    let mut r_sum: u32 = 0;
-    let mut g_sum: u32 = 0;
-    let mut b_sum: u32 = 0;
-    let mut count: u32 = 0;
+    let mut r_sum: u64 = 0;
+    let mut g_sum: u64 = 0;
+    let mut b_sum: u64 = 0;
+    let mut count: u64 = 0;
     for (_x, _y, pixel) in img.pixels() {
-        r_sum += pixel.data[0] as u32;
-        g_sum += pixel.data[1] as u32;
-        b_sum += pixel.data[2] as u32;
-        count += 1;
+        let alpha = pixel.data[3] as u64;
+        r_sum += pixel.data[0] as u64 * alpha;
+        g_sum += pixel.data[1] as u64 * alpha;
+        b_sum += pixel.data[2] as u64 * alpha;
+        count += alpha;
+    }
+    if count == 0 {
+        return Rgba {
+            data: [0, 0, 0, 255],
+        };
     }
 
     let r_avg = r_sum / count;
@@ -62,49 +135,246 @@ pub fn get_average_color(img: Arc<DynamicImage>) -> Rgba<u8> {
     }
 }
 
-pub fn image_area_diff(img1: Arc<DynamicImage>, img2: &RgbaImage, bounds: &[Point; 2]) -> f32 {
-    check_bounds(&bounds);
-    let mut total: u64 = 0;
-    let mut count: u64 = 0;
-    for x in bounds[0].x as u32..bounds[1].x as u32 {
-        for y in bounds[0].y as u32..bounds[1].y as u32 {
-            let p1 = img1.get_pixel(x, y);
-            let p2 = img2.get_pixel(x, y);
-            let r1 = p1.data[0] as i32;
-            let g1 = p1.data[1] as i32;
-            let b1 = p1.data[2] as i32;
-            let r2 = p2.data[0] as i32;
-            let g2 = p2.data[1] as i32;
-            let b2 = p2.data[2] as i32;
-            let r_diff: i32 = r1 - r2;
-            let g_diff: i32 = g1 - g2;
-            let b_diff: i32 = b1 - b2;
-            total += ((r_diff * r_diff) + (g_diff * g_diff) + (b_diff * b_diff)) as u64;
-            count += 1;
+/**
+ * @brief      opacity of every pixel, row by row
+ *
+ * @return     values in `[0, 1]`, `None` when the image is fully opaque
+ */
+pub fn opacity(img: &DynamicImage) -> Option<Vec<f32>> {
+    let (width, height) = img.dimensions();
+    let mut opaque = true;
+    let mut data = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let alpha = img.get_pixel(x, y).data[3];
+            opaque = opaque && alpha == 255;
+            data.push(alpha as f32 / 255.0);
         }
     }
-    ((total / count) as f32).sqrt()
+    if opaque {
+        None
+    } else {
+        Some(data)
+    }
 }
 
-pub fn image_diff(img1: Arc<DynamicImage>, img2: &RgbaImage) -> f32 {
-    let mut total: u64 = 0;
-    let mut count: u64 = 0;
-    for (x, y, pixel) in img1.pixels() {
-        let r1 = pixel.data[0] as i32;
-        let g1 = pixel.data[1] as i32;
-        let b1 = pixel.data[2] as i32;
-        let pixel2 = img2.get_pixel(x, y);
-        let r2 = pixel2.data[0] as i32;
-        let g2 = pixel2.data[1] as i32;
-        let b2 = pixel2.data[2] as i32;
-        let r_diff: i32 = r1 - r2;
-        let g_diff: i32 = g1 - g2;
-        let b_diff: i32 = b1 - b2;
-        total +=
-            ((r_diff * r_diff) as u64 + (g_diff * g_diff) as u64 + (b_diff * b_diff) as u64) as u64;
-        count += 1;
-    }
-    ((total / count) as f32).sqrt()
+/// number of k-means rounds used by `dominant_colors`
+const KMEANS_ITERATIONS: usize = 16;
+/// pixels sampled by `dominant_colors`, larger images are sampled on a grid
+const KMEANS_SAMPLES: u32 = 4096;
+
+/**
+ * @brief      clusters the colors of the visible pixels of `img` with k-means
+ *
+ * @param      k     largest number of clusters
+ *
+ * @return     the cluster centers with the share of pixels they hold, largest
+ *             share first; empty clusters are dropped
+ */
+pub fn dominant_colors(img: &DynamicImage, k: usize) -> Vec<(Rgba<u8>, f32)> {
+    let (width, height) = img.dimensions();
+    let step = ((width * height / KMEANS_SAMPLES) as f32).sqrt().max(1.0) as u32;
+    let mut samples: Vec<[f32; 3]> = Vec::new();
+    for y in (0..height).step_by(step as usize) {
+        for x in (0..width).step_by(step as usize) {
+            let p = img.get_pixel(x, y);
+            if p.data[3] == 0 {
+                continue;
+            }
+            samples.push([p.data[0] as f32, p.data[1] as f32, p.data[2] as f32]);
+        }
+    }
+    if samples.is_empty() || k == 0 {
+        return Vec::new();
+    }
+
+    // start from the luminance quantiles so that the result is repeatable
+    let mut sorted = samples.clone();
+    sorted.sort_by(|a, b| luminance(a).partial_cmp(&luminance(b)).unwrap());
+    let k = k.min(samples.len());
+    let mut centers: Vec<[f32; 3]> = (0..k)
+        .map(|i| sorted[(2 * i + 1) * sorted.len() / (2 * k)])
+        .collect();
+
+    let mut assignment = vec![0; samples.len()];
+    for round in 0..KMEANS_ITERATIONS {
+        let mut changed = false;
+        for (sample, cluster) in samples.iter().zip(assignment.iter_mut()) {
+            let nearest = nearest_center(&centers, sample);
+            if nearest != *cluster {
+                *cluster = nearest;
+                changed = true;
+            }
+        }
+        if round > 0 && !changed {
+            break;
+        }
+        let mut sums = vec![[0.0f32; 3]; k];
+        let mut counts = vec![0usize; k];
+        for (sample, &cluster) in samples.iter().zip(assignment.iter()) {
+            for c in 0..3 {
+                sums[cluster][c] += sample[c];
+            }
+            counts[cluster] += 1;
+        }
+        for i in 0..k {
+            if counts[i] > 0 {
+                for c in 0..3 {
+                    centers[i][c] = sums[i][c] / counts[i] as f32;
+                }
+            }
+        }
+    }
+
+    let mut counts = vec![0usize; k];
+    for &cluster in &assignment {
+        counts[cluster] += 1;
+    }
+    let mut clusters: Vec<(Rgba<u8>, f32)> = centers
+        .iter()
+        .zip(counts.iter())
+        .filter(|&(_, &count)| count > 0)
+        .map(|(center, &count)| {
+            let color = Rgba {
+                data: [
+                    center[0].round() as u8,
+                    center[1].round() as u8,
+                    center[2].round() as u8,
+                    255,
+                ],
+            };
+            (color, count as f32 / samples.len() as f32)
+        })
+        .collect();
+    clusters.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+    clusters
+}
+
+fn luminance(c: &[f32; 3]) -> f32 {
+    0.299 * c[0] + 0.587 * c[1] + 0.114 * c[2]
+}
+
+fn nearest_center(centers: &[[f32; 3]], c: &[f32; 3]) -> usize {
+    let distance = |center: &[f32; 3]| {
+        (0..3)
+            .map(|i| (center[i] - c[i]) * (center[i] - c[i]))
+            .sum::<f32>()
+    };
+    (0..centers.len()).fold(0, |nearest, i| {
+        if distance(&centers[i]) < distance(&centers[nearest]) {
+            i
+        } else {
+            nearest
+        }
+    })
+}
+
+/// parses an opaque `#rrggbb` color
+pub fn parse_hex_color(s: &str) -> Option<Rgba<u8>> {
+    if s.len() != 7 || !s.starts_with('#') || !s.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&s[i..i + 2], 16).ok();
+    Some(Rgba {
+        data: [channel(1)?, channel(3)?, channel(5)?, 255],
+    })
+}
+
+/**
+ * @brief      change of the whole image sum of squared errors when `current`
+ *             is replaced by `candidate`
+ *
+ * The two rasters may only differ where the new shape was drawn, so only the
+ * pixels of `pixel_area(bounds)` are compared.
+ *
+ * @return     negative when `candidate` is closer to the target
+ */
+pub fn image_area_sse_delta(
+    target: Arc<DynamicImage>,
+    current: &RgbaImage,
+    candidate: &RgbaImage,
+    bounds: &[Point; 2],
+    weights: Option<&Weights>,
+) -> f64 {
+    let (width, height) = target.dimensions();
+    let (min_x, min_y, max_x, max_y) = pixel_area(bounds, width, height);
+    let mut delta = 0.0;
+    for x in min_x..max_x {
+        for y in min_y..max_y {
+            let expected = target.get_pixel(x, y);
+            let change = pixel_sse(&expected, candidate.get_pixel(x, y))
+                - pixel_sse(&expected, current.get_pixel(x, y));
+            delta += change * weight_at(weights, x, y);
+        }
+    }
+    delta
+}
+
+/**
+ * @brief      pixels that may change when a shape with `bounds` is drawn: the
+ *             bounds grown by one pixel for the anti-aliased edges and clamped
+ *             to the image
+ *
+ * @return     (min_x, min_y, max_x, max_y), the max values are exclusive
+ */
+pub fn pixel_area(bounds: &[Point; 2], width: u32, height: u32) -> (u32, u32, u32, u32) {
+    check_bounds(bounds);
+    let min_x = (bounds[0].x.floor() - 1.0).max(0.0) as u32;
+    let min_y = (bounds[0].y.floor() - 1.0).max(0.0) as u32;
+    let max_x = ((bounds[1].x.ceil() + 1.0).max(0.0) as u32).min(width);
+    let max_y = ((bounds[1].y.ceil() + 1.0).max(0.0) as u32).min(height);
+    (min_x, min_y, max_x, max_y)
+}
+
+/// weighted sum of squared pixel differences over the whole image
+pub fn image_sse(img1: Arc<DynamicImage>, img2: &RgbaImage, weights: Option<&Weights>) -> f64 {
+    img1.pixels()
+        .map(|(x, y, pixel)| pixel_sse(&pixel, img2.get_pixel(x, y)) * weight_at(weights, x, y))
+        .sum()
+}
+
+/// weighted squared error of every pixel, row by row
+pub fn pixel_errors(
+    img1: Arc<DynamicImage>,
+    img2: &RgbaImage,
+    weights: Option<&Weights>,
+) -> Vec<f32> {
+    let (width, height) = img1.dimensions();
+    let mut errors = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let error = pixel_sse(&img1.get_pixel(x, y), img2.get_pixel(x, y));
+            errors.push((error * weight_at(weights, x, y)) as f32);
+        }
+    }
+    errors
+}
+
+/// weighted rmse of the pixels, see `pixel_sse`
+pub fn image_diff(img1: Arc<DynamicImage>, img2: &RgbaImage, weights: Option<&Weights>) -> f32 {
+    let (width, height) = img1.dimensions();
+    let total = total_weight(weights, width, height);
+    if total <= 0.0 {
+        return 0.0;
+    }
+    (image_sse(img1, img2, weights) / total).sqrt() as f32
+}
+
+/**
+ * @brief      squared error of two pixels: the rgb channels premultiplied by
+ *             the alpha plus the alpha itself, so opaque pixels compare by
+ *             their rgb channels alone
+ */
+fn pixel_sse(p1: &Rgba<u8>, p2: &Rgba<u8>) -> f64 {
+    let a1 = p1.data[3] as f64 / 255.0;
+    let a2 = p2.data[3] as f64 / 255.0;
+    let mut sse = 0.0;
+    for c in 0..3 {
+        let diff = p1.data[c] as f64 * a1 - p2.data[c] as f64 * a2;
+        sse += diff * diff;
+    }
+    let alpha_diff = p1.data[3] as f64 - p2.data[3] as f64;
+    sse + alpha_diff * alpha_diff
 }
 
 fn check_bounds(bounds: &[Point; 2]) {
@@ -130,15 +400,188 @@ mod tests {
     #[test]
     fn test_image_diff() {
         let i = Arc::new(get_test_image());
-        let score = image_diff(i.clone(), &i.to_rgba());
+        let score = image_diff(i.clone(), &i.to_rgba(), None);
         assert_eq!(0.0, score);
     }
 
     #[test]
-    fn test_image_area_diff() {
+    fn test_image_area_sse_delta() {
         let i = Arc::new(get_test_image());
+        let current = i.to_rgba();
         let bounds = &[Point { x: 0.0, y: 0.0 }, Point { x: 10.0, y: 10.0 }];
-        let score = image_area_diff(i.clone(), &i.to_rgba(), bounds);
-        assert_eq!(0.0, score);
+        assert_eq!(
+            0.0,
+            image_area_sse_delta(i.clone(), &current, &current, bounds, None)
+        );
+
+        let mut candidate = current.clone();
+        candidate.put_pixel(
+            5,
+            5,
+            Rgba {
+                data: [0, 0, 0, 255],
+            },
+        );
+        // the only change is the pixel at (5, 5)
+        let delta = image_area_sse_delta(i.clone(), &current, &candidate, bounds, None);
+        assert_eq!(delta, image_sse(i.clone(), &candidate, None));
+    }
+
+    #[test]
+    fn test_sse_delta_matches_global_change() {
+        let i = Arc::new(get_test_image());
+        let mut current = i.to_rgba();
+        current.put_pixel(
+            20,
+            20,
+            Rgba {
+                data: [0, 0, 0, 255],
+            },
+        );
+        let mut candidate = current.clone();
+        // an edge pixel just outside the bounds is still counted
+        for x in 20..31 {
+            candidate.put_pixel(
+                x,
+                20,
+                Rgba {
+                    data: [255, 255, 255, 255],
+                },
+            );
+        }
+        let bounds = &[Point { x: 20.0, y: 20.0 }, Point { x: 30.0, y: 21.0 }];
+        let delta = image_area_sse_delta(i.clone(), &current, &candidate, bounds, None);
+        let global = image_sse(i.clone(), &candidate, None) - image_sse(i.clone(), &current, None);
+        assert_eq!(global, delta);
+    }
+
+    #[test]
+    fn test_pixel_errors() {
+        let i = Arc::new(get_test_image());
+        let (width, _) = i.dimensions();
+        let mut other = i.to_rgba();
+        let mut p = *other.get_pixel(3, 2);
+        p.data[0] = p.data[0].wrapping_add(10);
+        other.put_pixel(3, 2, p);
+        let errors = pixel_errors(i.clone(), &other, None);
+        assert_eq!(errors[(2 * width + 3) as usize], 100.0);
+        assert_eq!(errors.iter().sum::<f32>(), 100.0);
+    }
+
+    #[test]
+    fn test_weighted_image_diff() {
+        let i = Arc::new(get_test_image());
+        let (width, height) = i.dimensions();
+        let mut other = i.to_rgba();
+        other.put_pixel(
+            0,
+            0,
+            Rgba {
+                data: [0, 0, 0, 255],
+            },
+        );
+        // the only difference is in a pixel without weight
+        let mut data = vec![1.0; (width * height) as usize];
+        data[0] = 0.0;
+        let weights = Weights::from_data(width, height, data);
+        assert!(image_diff(i.clone(), &other, None) > 0.0);
+        assert_eq!(0.0, image_diff(i.clone(), &other, Some(&weights)));
+    }
+
+    #[test]
+    fn test_dominant_colors() {
+        // three quarters red, one quarter blue
+        let img = RgbaImage::from_fn(64, 64, |x, _| {
+            if x < 48 {
+                Rgba {
+                    data: [200, 10, 10, 255],
+                }
+            } else {
+                Rgba {
+                    data: [10, 10, 200, 255],
+                }
+            }
+        });
+        let colors = dominant_colors(&DynamicImage::ImageRgba8(img), 3);
+        assert_eq!(colors.len(), 2);
+        assert_eq!(colors[0].0.data, [200, 10, 10, 255]);
+        assert_eq!(colors[0].1, 0.75);
+        assert_eq!(colors[1].0.data, [10, 10, 200, 255]);
+    }
+
+    #[test]
+    fn test_transparent_pixels() {
+        let red = Rgba {
+            data: [255, 0, 0, 255],
+        };
+        let clear = Rgba {
+            data: [0, 255, 0, 0],
+        };
+        // invisible colors do not matter, visibility does
+        assert_eq!(pixel_sse(&clear, &Rgba { data: [9, 9, 9, 0] }), 0.0);
+        assert_eq!(pixel_sse(&red, &clear), 255.0 * 255.0 * 2.0);
+
+        let img = RgbaImage::from_fn(8, 8, |x, _| if x < 4 { red } else { clear });
+        let img = Arc::new(DynamicImage::ImageRgba8(img));
+        assert_eq!(get_average_color(img.clone()).data, [255, 0, 0, 255]);
+        let bounds = [Point { x: 2.0, y: 0.0 }, Point { x: 8.0, y: 8.0 }];
+        assert_eq!(
+            get_average_color_from_area(img.clone(), bounds).data,
+            [255, 0, 0, 255]
+        );
+        let opacity = opacity(&img).unwrap();
+        assert_eq!(opacity[3], 1.0);
+        assert_eq!(opacity[4], 0.0);
+        assert!(super::opacity(&get_test_image()).is_none());
+        assert_eq!(dominant_colors(&img, 2).len(), 1);
+    }
+
+    #[test]
+    fn test_average_color_along() {
+        // a blue frame around a red square
+        let img = RgbaImage::from_fn(8, 8, |x, y| {
+            let edge = x == 1 || x == 6 || y == 1 || y == 6;
+            Rgba {
+                data: if edge {
+                    [0, 0, 255, 255]
+                } else {
+                    [255, 0, 0, 255]
+                },
+            }
+        });
+        let img = Arc::new(DynamicImage::ImageRgba8(img));
+        let outline = [
+            Point { x: 1.0, y: 1.0 },
+            Point { x: 6.0, y: 1.0 },
+            Point { x: 6.0, y: 6.0 },
+            Point { x: 1.0, y: 6.0 },
+        ];
+        assert_eq!(
+            get_average_color_along(img.clone(), &outline).data,
+            [0, 0, 255, 255]
+        );
+        // the part of the outline off the image is left out
+        let overhanging = [
+            Point { x: 1.0, y: 1.0 },
+            Point { x: 20.0, y: 1.0 },
+            Point { x: 20.0, y: 6.0 },
+            Point { x: 1.0, y: 6.0 },
+        ];
+        assert_eq!(
+            get_average_color_along(img.clone(), &overhanging).data,
+            [0, 0, 255, 255]
+        );
+        assert_eq!(get_average_color_along(img, &[]).data, [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_parse_hex_color() {
+        assert_eq!(
+            parse_hex_color("#ff8000").map(|c| c.data),
+            Some([255, 128, 0, 255])
+        );
+        assert!(parse_hex_color("ff8000").is_none());
+        assert!(parse_hex_color("#ff80").is_none());
+        assert!(parse_hex_color("#gg8000").is_none());
     }
 }
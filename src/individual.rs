@@ -1,15 +1,35 @@
+use std::f32::consts::PI;
 use std::fmt;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+use color_mode::ColorMode;
 use error::Result;
 use image::save_buffer;
 use image::{ColorType, DynamicImage, GenericImage, Rgba, RgbaImage};
-use image_utils::{get_average_color_from_area, image_area_diff, image_diff, rgba_to_str};
-use nsvg;
+use image_utils::{get_average_color_along, get_average_color_from_area};
+use metric::Metrics;
+use model::{self, Model};
+use palette::Palette;
 use rand::distributions::{IndependentSample, Range};
-use rand::thread_rng;
-use shape::{Point, Polygon};
+use rand::Rng;
+use shape::{Gradient, GradientKind, Point, Polygon, ShapeLimits, Stroke, Style};
+use weights::Weights;
+
+const DEFAULT_ALPHA: u8 = 179;
+/// thinnest stroke in pixels
+const MIN_STROKE_WIDTH: f32 = 1.0;
+/// the widest stroke is the longest side of the canvas divided by this
+const STROKE_WIDTH_DIVISOR: f32 = 32.0;
+/// largest change of a gradient direction by `mutate`, in degrees
+const ANGLE_JITTER: f32 = 15.0;
+/// largest change of a channel of a gradient stop by `mutate`
+const STOP_JITTER: i32 = 16;
+/// points on the ellipse the end color of a radial gradient is sampled along
+const RIM_SAMPLES: usize = 16;
+/// the end color of a radial gradient is sampled this far out from the
+/// center, relative to the radius
+const RIM_RADIUS: f32 = 0.875;
 
 #[derive(Clone)]
 pub struct GImage {
@@ -18,21 +38,40 @@ pub struct GImage {
     id: u32,
     width: u32,
     height: u32,
-    avg_color: Rgba<u8>,
+    /// color below the polygons, fully transparent for no background
+    background: Rgba<u8>,
+    alpha: u8,
+    /// distance used by `fitness_full` and `fitness_mutation`
+    metric: Metrics,
+    /// importance of every pixel of the target, without weights all pixels
+    /// count the same
+    weights: Option<Arc<Weights>>,
+    /// size and form limits of the polygons added by `mutate`
+    limits: ShapeLimits,
+    /// colors the fills are snapped to
+    palette: Option<Arc<Palette>>,
+    /// how the fills are derived from the target
+    color_mode: ColorMode,
+    /// gradients `mutate` may fill the new polygon with, besides a flat color
+    gradients: Vec<GradientKind>,
+    /// whether `mutate` fills the new polygon, outlines it or both
+    style: Style,
     path: PathBuf,
 }
 
 pub trait Individual {
-    fn mutate(&self, shape: Polygon, new_id: u32) -> GImage;
-    fn fitness_full(&self) -> f32;
-    fn fitness_mutation(&self) -> f32;
+    fn mutate<R: Rng>(&self, rng: &mut R, shape: Polygon, new_id: u32) -> GImage;
+    fn fitness_full(&self) -> Result<f32>;
+    /// change of the whole image error caused by the last mutation compared
+    /// to `current`, the raster of the parent; negative is an improvement
+    fn fitness_mutation(&self, current: &RgbaImage) -> f64;
 }
 
 impl GImage {
     pub fn new(
         id: u32,
         i: Arc<DynamicImage>,
-        avg_color: Rgba<u8>,
+        background: Rgba<u8>,
         width: u32,
         height: u32,
     ) -> Self {
@@ -43,25 +82,229 @@ impl GImage {
             width: width,
             height: height,
             id: id,
-            avg_color: avg_color,
+            background: background,
+            alpha: DEFAULT_ALPHA,
+            metric: Metrics::default(),
+            weights: None,
+            limits: ShapeLimits::default(),
+            palette: None,
+            color_mode: ColorMode::default(),
+            gradients: Vec::new(),
+            style: Style::Fill,
+            path: PathBuf::from(format!("./tmp/{}", id)),
+        }
+    }
+
+    pub fn from_model(id: u32, i: Arc<DynamicImage>, model: Model) -> Self {
+        GImage {
+            target: i,
+            polygons: model.polygons,
+            width: model.width,
+            height: model.height,
+            id: id,
+            background: model.background,
+            alpha: DEFAULT_ALPHA,
+            metric: Metrics::default(),
+            weights: None,
+            limits: ShapeLimits::default(),
+            palette: None,
+            color_mode: ColorMode::default(),
+            gradients: Vec::new(),
+            style: Style::Fill,
             path: PathBuf::from(format!("./tmp/{}", id)),
         }
     }
 
+    /**
+     * @brief      sets the opacity of the polygons added by `mutate`
+     *
+     * @param      alpha  opacity between 0.0 and 1.0
+     */
+    pub fn set_alpha(&mut self, alpha: f32) {
+        self.alpha = (alpha.clamp(0.0, 1.0) * 255.0).round() as u8;
+    }
+
+    pub fn set_metric(&mut self, metric: Metrics) {
+        self.metric = metric;
+    }
+
+    pub fn set_weights(&mut self, weights: Option<Arc<Weights>>) {
+        self.weights = weights;
+    }
+
+    pub fn set_limits(&mut self, limits: ShapeLimits) {
+        self.limits = limits;
+    }
+
+    pub fn set_palette(&mut self, palette: Option<Arc<Palette>>) {
+        self.palette = palette;
+    }
+
+    pub fn set_color_mode(&mut self, color_mode: ColorMode) {
+        self.color_mode = color_mode;
+    }
+
+    pub fn set_gradients(&mut self, gradients: Vec<GradientKind>) {
+        self.gradients = gradients;
+    }
+
+    pub fn set_style(&mut self, style: Style) {
+        self.style = style;
+    }
+
+    /// `color` snapped to the palette and mapped by the color mode
+    fn paint(&self, color: Rgba<u8>) -> Rgba<u8> {
+        let color = match self.palette {
+            Some(ref palette) => palette.nearest(&color),
+            None => color,
+        };
+        self.color_mode.fill(color, self.alpha, self.background)
+    }
+
+    /**
+     * @brief      fills `polygon` with a gradient of `kind` whose stops are
+     *             the target colors at its ends, the center and the rim of a
+     *             radial one, jittered; a linear gradient gets a random
+     *             direction
+     */
+    fn shade<R: Rng>(&self, rng: &mut R, polygon: &mut Polygon, kind: GradientKind) {
+        let bounds = polygon.get_bounds();
+        let center = Point {
+            x: (bounds[0].x + bounds[1].x) / 2.0,
+            y: (bounds[0].y + bounds[1].y) / 2.0,
+        };
+        let (half_width, half_height) = (
+            (bounds[1].x - bounds[0].x) / 2.0,
+            (bounds[1].y - bounds[0].y) / 2.0,
+        );
+        let color_at = |p: &Point| {
+            let area = [
+                Point {
+                    x: (p.x - half_width / 2.0).max(bounds[0].x),
+                    y: (p.y - half_height / 2.0).max(bounds[0].y),
+                },
+                Point {
+                    x: (p.x + half_width / 2.0).min(bounds[1].x),
+                    y: (p.y + half_height / 2.0).min(bounds[1].y),
+                },
+            ];
+            get_average_color_from_area(self.target.clone(), area)
+        };
+        let (from, gradient) = match kind {
+            GradientKind::Linear => {
+                let angle: f32 = Range::new(0.0, 360.0).ind_sample(rng);
+                let (dx, dy) = (
+                    angle.to_radians().cos() * half_width,
+                    angle.to_radians().sin() * half_height,
+                );
+                let start = Point {
+                    x: center.x - dx,
+                    y: center.y - dy,
+                };
+                let end = Point {
+                    x: center.x + dx,
+                    y: center.y + dy,
+                };
+                let to = self.paint(jitter(rng, color_at(&end)));
+                (
+                    color_at(&start),
+                    Gradient::Linear {
+                        angle: angle,
+                        to: to,
+                    },
+                )
+            }
+            GradientKind::Radial => {
+                // the gradient spans the ellipse inscribed in the bounds
+                let rim: Vec<Point> = (0..RIM_SAMPLES)
+                    .map(|i| {
+                        let t = i as f32 * 2.0 * PI / RIM_SAMPLES as f32;
+                        Point {
+                            x: center.x + t.cos() * half_width * RIM_RADIUS,
+                            y: center.y + t.sin() * half_height * RIM_RADIUS,
+                        }
+                    })
+                    .collect();
+                let to = self.paint(jitter(
+                    rng,
+                    get_average_color_along(self.target.clone(), &rim),
+                ));
+                (color_at(&center), Gradient::Radial { to: to })
+            }
+        };
+        polygon.set_fill_color(self.paint(jitter(rng, from)));
+        polygon.set_gradient(Some(gradient));
+    }
+
+    /**
+     * @brief      varies the gradient of `polygon`: turns a linear one by up
+     *             to `ANGLE_JITTER` degrees and shifts both stops
+     */
+    fn vary<R: Rng>(&self, rng: &mut R, polygon: &mut Polygon, gradient: Gradient) {
+        let gradient = match gradient {
+            Gradient::Linear { angle, to } => {
+                let turn = Range::new(-ANGLE_JITTER, ANGLE_JITTER).ind_sample(rng);
+                Gradient::Linear {
+                    angle: (angle + turn + 360.0) % 360.0,
+                    to: self.paint(jitter(rng, to)),
+                }
+            }
+            Gradient::Radial { to } => Gradient::Radial {
+                to: self.paint(jitter(rng, to)),
+            },
+        };
+        let from = self.paint(jitter(rng, polygon.fill_color()));
+        polygon.set_fill_color(from);
+        polygon.set_gradient(Some(gradient));
+    }
+
+    /**
+     * @brief      outlines `polygon` in the target color along its edges
+     *             with a random width, from `MIN_STROKE_WIDTH` up to a
+     *             fraction of the canvas
+     */
+    fn outline<R: Rng>(&self, rng: &mut R, polygon: &mut Polygon) {
+        let max_width =
+            (self.width.max(self.height) as f32 / STROKE_WIDTH_DIVISOR).max(MIN_STROKE_WIDTH + 1.0);
+        let width = Range::new(MIN_STROKE_WIDTH, max_width).ind_sample(rng);
+        // along the whole outline, a stroke is not clipped to the canvas
+        let color = self.paint(get_average_color_along(
+            self.target.clone(),
+            &polygon.points,
+        ));
+        polygon.set_stroke(Some(Stroke {
+            width: width,
+            color: color,
+        }));
+    }
+
+    pub fn to_model(&self) -> Model {
+        Model::new(
+            self.width,
+            self.height,
+            self.background,
+            self.polygons.clone(),
+        )
+    }
+
     /**
      * @brief      returns the bounds of the last mutation
      *
      * @param      &self
      *
-     * @return     &[Point]
+     * @return     the bounds including the stroke, `None` when there are no
+     *             polygons yet
      */
-    pub fn mutation_area(&self) -> [Point; 2] {
-        let last_polygon = self.polygons.last().unwrap();
-        last_polygon.get_bounds()
+    pub fn mutation_area(&self) -> Option<[Point; 2]> {
+        self.polygons.last().map(|polygon| polygon.covered_bounds())
     }
 
-    pub fn get_last_polygon(&self) -> Polygon {
-        self.polygons.last().unwrap().clone()
+    pub fn get_last_polygon(&self) -> Option<Polygon> {
+        self.polygons.last().cloned()
+    }
+
+    pub fn num_polygons(&self) -> usize {
+        self.polygons.len()
     }
 
     pub fn add_polygon(&mut self, polygon: Polygon) {
@@ -78,33 +321,27 @@ impl GImage {
         }
     }
 
+    fn weights(&self) -> Option<&Weights> {
+        self.weights.as_deref()
+    }
+
     pub fn id(&self) -> u32 {
         self.id
     }
 
     fn svg_as_string(&self) -> String {
-        let mut polygons = String::new();
-        for polygon in &self.polygons {
-            // let mut bounds = polygon.get_bounds();
-            // let fill_color = get_average_color_from_area(self.target.clone(), bounds);
-            polygons.push_str(polygon.svg().as_str())
-        }
-        let mut svg =
-            String::from(
-                format!(
-            "<svg width=\"{}\" height=\"{}\" viewbox=\"0 0 {} {}\" version=\"1.1\" xmlns=\"http://www.w3.org/2000/svg\">
-            <rect width=\"{}\" height=\"{}\" x=\"0\" y=\"0\" fill=\"rgb({})\" fill-opacity=\"0.9\"/>",
-            self.width, self.height, self.width, self.height, self.width, self.height, rgba_to_str(&self.avg_color)),
-            );
-        svg.push_str(polygons.as_str());
-        svg.push_str("</svg>");
-        svg
+        model::svg_document(
+            self.width,
+            self.height,
+            &self.background,
+            &self.polygons,
+            (self.width, self.height),
+            true,
+        )
     }
 
     fn raster(&self) -> Result<RgbaImage> {
-        let svg = nsvg::parse_str(&self.svg_as_string(), nsvg::Units::Pixel, 96.0)?;
-        let scale = 1.0;
-        Ok(svg.rasterize(scale)?)
+        model::rasterize(&self.svg_as_string())
     }
 
     pub fn save_raster(&self, path: &Path) -> Result<()> {
@@ -125,19 +362,40 @@ impl fmt::Debug for GImage {
 }
 
 impl Individual for GImage {
-    fn mutate(&self, mut candidate: Polygon, new_id: u32) -> GImage {
+    fn mutate<R: Rng>(&self, rng: &mut R, mut candidate: Polygon, new_id: u32) -> GImage {
         let bounds = candidate.get_bounds();
-        let fill_color = get_average_color_from_area(self.target.clone(), bounds);
+        let fill_color = self.paint(get_average_color_from_area(self.target.clone(), bounds));
         candidate.set_fill_color(fill_color);
-        let mut rng = thread_rng();
+        // scaling and constraining move the shape, it stays where it was
+        // placed
+        let center = candidate.center();
         let scale_generator = Range::new(0.5, 2.0);
-        let scale_x = scale_generator.ind_sample(&mut rng);
-        let scale_y = scale_generator.ind_sample(&mut rng);
+        let scale_x = scale_generator.ind_sample(rng);
+        let scale_y = scale_generator.ind_sample(rng);
         // debug!("scale factors (x, y): {} {}", scale_x, scale_y);
         candidate.scale(&scale_x, &scale_y);
         let angle_generator = Range::new(0, 91);
-        let angle = angle_generator.ind_sample(&mut rng) as f32;
+        let angle = angle_generator.ind_sample(rng) as f32;
         candidate.rotate(&angle);
+        candidate.constrain(&self.limits);
+        candidate.move_to(&center);
+        if self.style == Style::Stroke {
+            // the outline only, an invisible fill without a gradient
+            candidate.set_fill_color(Rgba { data: [0, 0, 0, 0] });
+            candidate.set_gradient(None);
+        } else if let Some(gradient) = candidate.gradient() {
+            // a shaded candidate keeps its kind of gradient
+            self.vary(rng, &mut candidate, gradient);
+        } else if !self.gradients.is_empty() {
+            // a flat fill stays one of the choices
+            let choice = Range::new(0, self.gradients.len() + 1).ind_sample(rng);
+            if let Some(&kind) = self.gradients.get(choice) {
+                self.shade(rng, &mut candidate, kind);
+            }
+        }
+        if self.style != Style::Fill {
+            self.outline(rng, &mut candidate);
+        }
         let mut v: Vec<Polygon> = self.polygons.clone();
         v.push(candidate);
         GImage {
@@ -146,31 +404,61 @@ impl Individual for GImage {
             width: self.width,
             height: self.height,
             id: new_id,
-            avg_color: self.avg_color,
+            background: self.background,
+            alpha: self.alpha,
+            metric: self.metric,
+            weights: self.weights.clone(),
+            limits: self.limits,
+            palette: self.palette.clone(),
+            color_mode: self.color_mode,
+            gradients: self.gradients.clone(),
+            style: self.style,
             path: PathBuf::from(format!("./tmp/{}", new_id)),
         }
     }
 
-    fn fitness_mutation(&self) -> f32 {
+    fn fitness_mutation(&self, current: &RgbaImage) -> f64 {
+        let area = match self.mutation_area() {
+            Some(area) => area,
+            None => return f64::INFINITY,
+        };
         match self.raster() {
-            Ok(r) => image_area_diff(self.target.clone(), &r, &self.mutation_area()),
+            Ok(r) => {
+                self.metric
+                    .get()
+                    .delta(self.target.clone(), current, &r, &area, self.weights())
+            }
             Err(e) => {
                 error!("error rasterizing individual {}: {}", self.id, e);
-                9999.0
+                f64::INFINITY
             }
         }
     }
 
-    fn fitness_full(&self) -> f32 {
-        image_diff(self.target.clone(), &self.as_rgba_img().unwrap())
+    fn fitness_full(&self) -> Result<f32> {
+        Ok(self
+            .metric
+            .get()
+            .score(self.target.clone(), &self.as_rgba_img()?, self.weights()))
     }
 }
 
+/// `color` with every channel but alpha shifted by up to `STOP_JITTER`
+fn jitter<R: Rng>(rng: &mut R, color: Rgba<u8>) -> Rgba<u8> {
+    let shift = Range::new(-STOP_JITTER, STOP_JITTER + 1);
+    let mut data = color.data;
+    for channel in data.iter_mut().take(3) {
+        *channel = (*channel as i32 + shift.ind_sample(rng)).clamp(0, 255) as u8;
+    }
+    Rgba { data: data }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use image::Rgba;
     use image_utils;
+    use rand::thread_rng;
     use shape::Shapes;
     use std::path::Path;
     use std::process;
@@ -187,7 +475,7 @@ mod tests {
         let (width, height) = (1, 1);
         let mut g_img = GImage::new(1, Arc::new(img), Rgba { data: [0, 0, 0, 0] }, width, height);
         let (width, height) = (1.0, 1.0);
-        let polygon = Polygon::new(Shapes::Rectangle, width, height);
+        let polygon = Polygon::new(&mut thread_rng(), Shapes::Rectangle, width, height);
         g_img.add_polygon(polygon);
         assert!(g_img.polygons.len() == 1);
     }
@@ -203,15 +491,183 @@ mod tests {
         let (width, height) = (1, 1);
         let mut g_img = GImage::new(1, Arc::new(img), Rgba { data: [0, 0, 0, 0] }, width, height);
         let (width, height) = (1.0, 1.0);
-        let polygon_1 = Polygon::new(Shapes::Rectangle, width, height);
+        let polygon_1 = Polygon::new(&mut thread_rng(), Shapes::Rectangle, width, height);
         g_img.add_polygon(polygon_1.clone());
-        assert!(g_img.get_last_polygon().points == polygon_1.points);
+        assert!(g_img.get_last_polygon().unwrap().points == polygon_1.points);
         // polygon should still be there
         assert!(g_img.polygons.len() == 1);
-        let polygon_2 = Polygon::new(Shapes::Rectangle, width, height);
+        let polygon_2 = Polygon::new(&mut thread_rng(), Shapes::Rectangle, width, height);
         g_img.add_polygon(polygon_2.clone());
         assert!(g_img.polygons.len() == 2);
-        assert!(g_img.get_last_polygon().points == polygon_2.points);
+        assert!(g_img.get_last_polygon().unwrap().points == polygon_2.points);
+    }
+
+    #[test]
+    fn should_mutate_with_gradients() {
+        // gets brighter to the right
+        let img = RgbaImage::from_fn(100, 100, |x, _| Rgba {
+            data: [(x * 2) as u8, 0, 0, 255],
+        });
+        let mut g_img = GImage::new(
+            1,
+            Arc::new(DynamicImage::ImageRgba8(img)),
+            Rgba {
+                data: [0, 0, 0, 255],
+            },
+            100,
+            100,
+        );
+        g_img.set_gradients(vec![GradientKind::Linear]);
+        let (mut flat, mut shaded) = (0, 0);
+        for i in 0..50 {
+            let polygon = Polygon::new(&mut thread_rng(), Shapes::Rectangle, 100.0, 100.0);
+            let polygon = g_img
+                .mutate(&mut thread_rng(), polygon, i)
+                .get_last_polygon()
+                .unwrap();
+            match polygon.gradient() {
+                Some(Gradient::Linear { angle, to }) => {
+                    shaded += 1;
+                    // both stops are jittered
+                    let (from, to) = (polygon.fill_color().data[0] as i32, to.data[0] as i32);
+                    if angle.to_radians().cos() > 0.1 {
+                        assert!(to + 2 * STOP_JITTER >= from);
+                    } else if angle.to_radians().cos() < -0.1 {
+                        assert!(to <= from + 2 * STOP_JITTER);
+                    }
+                }
+                Some(_) => panic!("only linear gradients are allowed"),
+                None => flat += 1,
+            }
+        }
+        assert!(flat > 0 && shaded > 0);
+    }
+
+    #[test]
+    fn should_end_radial_gradients_at_the_rim() {
+        // a white disc on black, the box around it is mostly black
+        let img = RgbaImage::from_fn(100, 100, |x, y| {
+            let (dx, dy) = (x as f32 - 50.0, y as f32 - 50.0);
+            let v = if dx * dx + dy * dy < 400.0 { 255 } else { 0 };
+            Rgba {
+                data: [v, v, v, 255],
+            }
+        });
+        let g_img = GImage::new(
+            1,
+            Arc::new(DynamicImage::ImageRgba8(img)),
+            Rgba {
+                data: [0, 0, 0, 255],
+            },
+            100,
+            100,
+        );
+        let points = vec![
+            Point { x: 20.0, y: 20.0 },
+            Point { x: 80.0, y: 20.0 },
+            Point { x: 80.0, y: 80.0 },
+            Point { x: 20.0, y: 80.0 },
+        ];
+        let mut polygon = Polygon::from_points(
+            Shapes::Rectangle,
+            points,
+            100.0,
+            100.0,
+            Rgba { data: [0; 4] },
+        );
+        g_img.shade(&mut thread_rng(), &mut polygon, GradientKind::Radial);
+        assert!(polygon.fill_color().data[0] > 192);
+        match polygon.gradient() {
+            Some(Gradient::Radial { to }) => assert!(to.data[0] <= STOP_JITTER as u8),
+            _ => panic!("expected a radial gradient"),
+        }
+    }
+
+    #[test]
+    fn should_vary_gradients() {
+        let img = RgbaImage::from_pixel(
+            64,
+            64,
+            Rgba {
+                data: [100, 100, 100, 255],
+            },
+        );
+        let mut g_img = GImage::new(
+            1,
+            Arc::new(DynamicImage::ImageRgba8(img)),
+            Rgba {
+                data: [0, 0, 0, 255],
+            },
+            64,
+            64,
+        );
+        g_img.set_gradients(vec![GradientKind::Linear]);
+        let mut polygon = Polygon::new(&mut thread_rng(), Shapes::Rectangle, 64.0, 64.0);
+        polygon.set_gradient(Some(Gradient::Linear {
+            angle: 90.0,
+            to: Rgba {
+                data: [50, 50, 50, 179],
+            },
+        }));
+        let (mut turned, mut shifted) = (false, false);
+        for i in 0..20 {
+            let mutated = g_img.mutate(&mut thread_rng(), polygon.clone(), i);
+            match mutated.get_last_polygon().unwrap().gradient() {
+                Some(Gradient::Linear { angle, to }) => {
+                    assert!((angle - 90.0).abs() <= ANGLE_JITTER);
+                    assert!((to.data[0] as i32 - 50).abs() <= STOP_JITTER);
+                    turned |= angle != 90.0;
+                    shifted |= to.data[0] != 50;
+                }
+                _ => panic!("expected the linear gradient to be kept"),
+            }
+        }
+        assert!(turned && shifted);
+    }
+
+    #[test]
+    fn should_mutate_with_strokes() {
+        let img = RgbaImage::from_pixel(
+            64,
+            64,
+            Rgba {
+                data: [0, 128, 0, 255],
+            },
+        );
+        let mut g_img = GImage::new(
+            1,
+            Arc::new(DynamicImage::ImageRgba8(img)),
+            Rgba {
+                data: [0, 0, 0, 255],
+            },
+            64,
+            64,
+        );
+        g_img.set_gradients(vec![GradientKind::Radial]);
+        g_img.set_style(Style::Stroke);
+        for i in 0..20 {
+            let polygon = Polygon::new(&mut thread_rng(), Shapes::Triangle, 64.0, 64.0);
+            let mutated = g_img.mutate(&mut thread_rng(), polygon, i);
+            let polygon = mutated.get_last_polygon().unwrap();
+            assert_eq!(polygon.fill_color().data[3], 0);
+            assert!(polygon.gradient().is_none());
+            let stroke = polygon.stroke().unwrap();
+            assert!(stroke.width >= MIN_STROKE_WIDTH && stroke.width < 2.0);
+            assert_eq!(stroke.color.data, [0, 128, 0, 179]);
+            // the fitness covers the stroke outside of the polygon
+            let area = mutated.mutation_area().unwrap();
+            let bounds = polygon.get_bounds();
+            assert!(area[0].x <= bounds[0].x && area[1].y >= bounds[1].y);
+        }
+
+        g_img.set_style(Style::Both);
+        let polygon = Polygon::new(&mut thread_rng(), Shapes::Rectangle, 64.0, 64.0);
+        let polygon = g_img
+            .mutate(&mut thread_rng(), polygon, 21)
+            .get_last_polygon()
+            .unwrap();
+        assert_eq!(polygon.fill_color().data[3], 179);
+        assert!(polygon.stroke().is_some());
     }
 
     #[test]
@@ -225,21 +681,21 @@ mod tests {
         let (width, height) = (1, 1);
         let mut g_img = GImage::new(1, Arc::new(img), Rgba { data: [0, 0, 0, 0] }, width, height);
         let (width, height) = (100.0, 100.0);
-        let polygon = Polygon::new(Shapes::Rectangle, width, height);
-        let mutation = g_img.mutate(polygon.clone(), 2);
+        let polygon = Polygon::new(&mut thread_rng(), Shapes::Rectangle, width, height);
+        let mutation = g_img.mutate(&mut thread_rng(), polygon.clone(), 2);
         // should contain the mutation
         assert!(mutation.polygons.len() == 1);
         // should not have changed
         assert!(g_img.polygons.len() == 0);
         // should integrate mutation
-        g_img.add_polygon(mutation.get_last_polygon());
+        g_img.add_polygon(mutation.get_last_polygon().unwrap());
         assert!(g_img.polygons.len() == 1);
         // mutate again
-        let polygon = Polygon::new(Shapes::Rectangle, width, height);
-        let mutation = g_img.mutate(polygon.clone(), 3);
+        let polygon = Polygon::new(&mut thread_rng(), Shapes::Rectangle, width, height);
+        let mutation = g_img.mutate(&mut thread_rng(), polygon.clone(), 3);
         assert!(mutation.polygons.len() == 2);
         // integrate again
-        g_img.add_polygon(mutation.get_last_polygon());
+        g_img.add_polygon(mutation.get_last_polygon().unwrap());
         assert!(g_img.polygons.len() == 2);
     }
 }
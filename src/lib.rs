@@ -1,25 +1,126 @@
 #[macro_use]
 extern crate log;
-#[macro_use]
-extern crate failure;
 extern crate image;
 extern crate nsvg;
 extern crate rand;
 extern crate scoped_threadpool;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate toml;
 
+#[cfg(test)]
+#[macro_use]
+extern crate quickcheck;
+
+pub mod batch;
+mod color_mode;
+pub mod config;
 mod error;
 mod image_utils;
 mod individual;
+pub mod metric;
+pub mod model;
+mod palette;
 mod rectangle;
 mod shape;
 pub mod simulation;
+mod stamp;
+mod target;
 mod triangle;
+mod weights;
 
+pub use color_mode::ColorMode;
+pub use error::{Error, Result};
+use image::{DynamicImage, GenericImage};
+pub use individual::GImage;
+pub use metric::{Metric, Metrics};
+pub use model::Model;
+pub use palette::Palette;
+pub use shape::{Gradient, GradientKind, Point, Polygon, Shapes, Stroke, Style};
+pub use stamp::Stamp;
 use std::path::Path;
 use std::sync::Arc;
+pub use target::Target;
+pub use weights::{AutoWeights, Weights};
 
-pub fn run(img_path: &Path, options: Option<simulation::Options>) -> error::Result<()> {
-    let img = image_utils::load_image(img_path)?;
+/// decodes a target given as path, image or encoded buffer
+pub fn load_image<'a, T: Into<Target<'a>>>(target: T) -> Result<DynamicImage> {
+    target.into().load()
+}
+
+pub fn run<'a, T: Into<Target<'a>>>(
+    target: T,
+    options: Option<simulation::Options>,
+) -> Result<Model> {
+    let img = target.into().load()?;
 
     simulation::run(Arc::new(img), options.unwrap_or_default())
 }
+
+/// continue optimizing the polygons of a shape file written by `run`
+pub fn resume<'a, T: Into<Target<'a>>>(
+    target: T,
+    shapes_path: &Path,
+    options: Option<simulation::Options>,
+) -> Result<Model> {
+    let img = target.into().load()?;
+    let model = Model::load_shapes(shapes_path)?;
+
+    simulation::resume(Arc::new(img), model, options.unwrap_or_default())
+}
+
+/// rasterize a shape file, the output format is picked from the extension of
+/// `out_path` (`.svg` or any raster format supported by `image`)
+pub fn render(shapes_path: &Path, out_path: &Path, width: Option<u32>) -> Result<()> {
+    let model = Model::load_shapes(shapes_path)?;
+    let size = match width {
+        Some(0) => {
+            return Err(Error::InvalidOptions(
+                "render width must be greater than zero".to_owned(),
+            ))
+        }
+        Some(w) => {
+            let height = (model.height as f32 * w as f32 / model.width as f32).round();
+            (w, (height as u32).max(1))
+        }
+        None => model.size(),
+    };
+
+    model.save(out_path, size)?;
+    info!(
+        "rendered {} to {}",
+        shapes_path.display(),
+        out_path.display()
+    );
+
+    Ok(())
+}
+
+/// compare two images of the same size by their rmse, lower is better
+pub fn score<'a, 'b, A, B>(img: A, other: B) -> Result<f32>
+where
+    A: Into<Target<'a>>,
+    B: Into<Target<'b>>,
+{
+    score_with_metric(img, other, Metrics::Rmse)
+}
+
+/// compare two images of the same size with `metric`, lower is better
+pub fn score_with_metric<'a, 'b, A, B>(img: A, other: B, metric: Metrics) -> Result<f32>
+where
+    A: Into<Target<'a>>,
+    B: Into<Target<'b>>,
+{
+    let img = img.into().load()?;
+    let other = other.into().load()?;
+    if img.dimensions() != other.dimensions() {
+        return Err(Error::SizeMismatch {
+            expected: img.dimensions(),
+            found: other.dimensions(),
+        });
+    }
+
+    Ok(metric.get().score(Arc::new(img), &other.to_rgba(), None))
+}
@@ -1,73 +1,445 @@
 #[macro_use]
 extern crate clap;
 extern crate env_logger;
+extern crate image;
 
 extern crate geoshaper;
 
-use geoshaper::simulation;
+use geoshaper::simulation::{Event, Simulation};
+use geoshaper::{batch, config, simulation, Error, Model};
 
-use clap::{App, Arg};
-use std::path::Path;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+use image::DynamicImage;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
 use std::process;
+use std::str::FromStr;
+use std::sync::Arc;
 
 fn main() {
     env_logger::init();
 
+    let image_arg = Arg::with_name("image")
+        .short("i")
+        .long("image")
+        .value_name("FILE")
+        .help("target image, `-` reads it from stdin")
+        .takes_value(true)
+        .required(true);
+    let simulation_args = [
+        Arg::with_name("config")
+            .short("c")
+            .long("config")
+            .value_name("FILE")
+            .help("toml or json file with simulation options, flags take precedence")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("output")
+            .short("o")
+            .long("output")
+            .value_name("FILE")
+            .help("raster or svg output, the shape file is written next to it")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("shape")
+            .short("s")
+            .long("shape")
+            .value_name("STRING")
+            .help("shape used to mimic image")
+            .takes_value(true)
+            .possible_values(&["rectangle", "triangle"])
+            .required(false),
+        Arg::with_name("stamp")
+            .long("stamp")
+            .value_name("FILE")
+            .help("use the single path of an svg file as the shape")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("metric")
+            .long("metric")
+            .value_name("METRIC")
+            .help("distance to the target that is minimized, ssim and ms-ssim compare the luma of 8x8 blocks")
+            .takes_value(true)
+            .possible_values(&["rmse", "ssim", "ms-ssim", "lab"])
+            .required(false),
+        Arg::with_name("weights")
+            .long("weights")
+            .value_name("FILE")
+            .help("grayscale mask, bright areas are reproduced with more fidelity")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("autoweights")
+            .long("auto-weights")
+            .value_name("MAP")
+            .help("weight map computed from the target")
+            .takes_value(true)
+            .possible_values(&["edges", "saliency"])
+            .conflicts_with("weights")
+            .required(false),
+        Arg::with_name("placement")
+            .long("placement")
+            .value_name("STRING")
+            .help("where new shapes are placed, error favors the worst areas")
+            .takes_value(true)
+            .possible_values(&["uniform", "error"])
+            .required(false),
+        Arg::with_name("minshapesize")
+            .long("min-shape-size")
+            .value_name("SIZE")
+            .help("smallest shape, in pixels or as percentage of the canvas")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("maxshapesize")
+            .long("max-shape-size")
+            .value_name("SIZE")
+            .help("largest shape, in pixels or as percentage of the canvas")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("maxaspectratio")
+            .long("max-aspect-ratio")
+            .value_name("FLOAT")
+            .help("longest edge of a shape divided by its extent across it")
+            .takes_value(true)
+            .validator(is_number)
+            .required(false),
+        Arg::with_name("sizeschedule")
+            .long("size-schedule")
+            .value_name("SCHEDULE")
+            .help("shrink the largest shape as shapes are added")
+            .takes_value(true)
+            .possible_values(&["linear", "exponential", "step"])
+            .required(false),
+        Arg::with_name("background")
+            .long("background")
+            .value_name("BACKGROUND")
+            .help("color below the shapes: auto, mean, dominant, transparent or #rrggbb")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("gradients")
+            .long("gradients")
+            .value_name("KINDS")
+            .help("allow gradient fills: linear, radial or linear,radial")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("style")
+            .long("style")
+            .value_name("STYLE")
+            .help("fill the shapes, outline them with a stroke or both")
+            .takes_value(true)
+            .possible_values(&["fill", "stroke", "both"])
+            .required(false),
+        Arg::with_name("colormode")
+            .long("color-mode")
+            .value_name("MODE")
+            .help("how shape colors are chosen")
+            .takes_value(true)
+            .possible_values(&["color", "grayscale", "mono", "duotone"])
+            .required(false),
+        Arg::with_name("inks")
+            .long("inks")
+            .value_name("COLORS")
+            .help("ink of mono (#rrggbb) or the two colors of duotone (#rrggbb,#rrggbb)")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("palette")
+            .long("palette")
+            .value_name("FILE")
+            .help("paint with the colors of a palette file, one color per line or .gpl")
+            .takes_value(true)
+            .conflicts_with("palettesize")
+            .required(false),
+        Arg::with_name("palettesize")
+            .long("palette-size")
+            .value_name("INTEGER")
+            .help("paint with this many colors extracted from the target")
+            .takes_value(true)
+            .validator(is_positive_integer)
+            .required(false),
+        Arg::with_name("debug")
+            .short("d")
+            .long("debug")
+            .help("render incremental rasters")
+            .takes_value(false)
+            .required(false),
+        Arg::with_name("nodebug")
+            .long("no-debug")
+            .help("do not render incremental rasters, even if the config file asks for them")
+            .takes_value(false)
+            .conflicts_with("debug")
+            .required(false),
+        Arg::with_name("maxiter")
+            .short("m")
+            .long("maxiter")
+            .value_name("INTEGER")
+            .help("maximum number of iterations")
+            .takes_value(true)
+            .validator(is_positive_integer)
+            .required(false),
+        Arg::with_name("genes")
+            .short("g")
+            .long("genes")
+            .value_name("INTEGER")
+            .help("number of candidate mutations scored per iteration")
+            .takes_value(true)
+            .validator(is_positive_integer)
+            .required(false),
+        Arg::with_name("alpha")
+            .short("a")
+            .long("alpha")
+            .value_name("FLOAT")
+            .help("opacity of the shapes, between 0 and 1")
+            .takes_value(true)
+            .validator(is_number)
+            .required(false),
+        Arg::with_name("seed")
+            .long("seed")
+            .value_name("INTEGER")
+            .help("seed for the random generator, makes runs repeatable")
+            .takes_value(true)
+            .validator(is_integer)
+            .required(false),
+        Arg::with_name("targetscore")
+            .long("target-score")
+            .value_name("FLOAT")
+            .help("stop once the score of the whole image is at or below this value")
+            .takes_value(true)
+            .validator(is_number)
+            .required(false),
+        Arg::with_name("timelimit")
+            .long("time-limit")
+            .value_name("SECONDS")
+            .help("stop after this many seconds")
+            .takes_value(true)
+            .validator(is_positive_integer)
+            .required(false),
+        Arg::with_name("maxshapes")
+            .long("max-shapes")
+            .value_name("INTEGER")
+            .help("stop once the result holds this many shapes")
+            .takes_value(true)
+            .validator(is_positive_integer)
+            .required(false),
+        Arg::with_name("stagnation")
+            .long("stagnation")
+            .value_name("INTEGER")
+            .help("stop after this many consecutive iterations without improvement")
+            .takes_value(true)
+            .validator(is_positive_integer)
+            .required(false),
+        Arg::with_name("threads")
+            .short("t")
+            .long("threads")
+            .value_name("INTEGER")
+            .help("number of worker threads")
+            .takes_value(true)
+            .validator(is_positive_integer)
+            .required(false),
+        Arg::with_name("maxsize")
+            .long("max-size")
+            .value_name("INTEGER")
+            .help("downscale the target to this many pixels on its longest side while optimizing")
+            .takes_value(true)
+            .validator(is_positive_integer)
+            .required(false),
+    ];
+
     let matches = App::new("geoshaper")
         .version(crate_version!())
         .author(crate_authors!(",\n"))
-        .arg(
-            Arg::with_name("image")
-                .short("i")
-                .long("image")
-                .value_name("FILE")
-                .help("target image")
-                .takes_value(true)
-                .required(true),
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("approximate an image with geometric shapes")
+                .arg(image_arg.clone())
+                .args(&simulation_args),
+        )
+        .subcommand(
+            SubCommand::with_name("resume")
+                .about("continue a run from a shape file")
+                .arg(image_arg.clone())
+                .arg(
+                    Arg::with_name("shapes")
+                        .value_name("SHAPES")
+                        .help("shape file written by a previous run")
+                        .required(true),
+                )
+                .args(&simulation_args),
         )
-        .arg(
-            Arg::with_name("shape")
-                .short("s")
-                .long("shape")
-                .value_name("STRING")
-                .help("shape used to mimic image")
-                .takes_value(true)
-                .required(false),
+        .subcommand(
+            SubCommand::with_name("batch")
+                .about("approximate every image of a directory")
+                .arg(
+                    Arg::with_name("input")
+                        .value_name("INPUT_DIR")
+                        .help("directory with the target images")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("out")
+                        .long("out")
+                        .value_name("DIR")
+                        .help("directory for the results, named after the inputs")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("process images whose results are newer than them too")
+                        .required(false),
+                )
+                .args(&simulation_args),
         )
-        .arg(
-            Arg::with_name("debug")
-                .short("d")
-                .long("debug")
-                .help("render incremental rasters")
-                .takes_value(false)
-                .required(false),
+        .subcommand(
+            SubCommand::with_name("config")
+                .about("validate the effective configuration")
+                .args(&simulation_args)
+                .arg(
+                    Arg::with_name("dump")
+                        .long("dump")
+                        .help("print the effective configuration")
+                        .takes_value(false)
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .short("f")
+                        .long("format")
+                        .value_name("STRING")
+                        .help("format used by --dump")
+                        .takes_value(true)
+                        .possible_values(&["toml", "json"])
+                        .default_value("toml")
+                        .required(false),
+                ),
         )
-        .arg(
-            Arg::with_name("maxiter")
-                .short("mi")
-                .long("maxiter")
-                .value_name("INTEGER")
-                .help("maximum number of iterations")
-                .takes_value(true)
-                .required(false),
+        .subcommand(
+            SubCommand::with_name("render")
+                .about("render a shape file to PNG or SVG")
+                .arg(
+                    Arg::with_name("shapes")
+                        .value_name("SHAPES")
+                        .help("shape file written by a previous run")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("output file, format is picked from the extension")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("width")
+                        .short("w")
+                        .long("width")
+                        .value_name("INTEGER")
+                        .help("output width in pixels, keeps the aspect ratio")
+                        .takes_value(true)
+                        .validator(is_positive_integer)
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("score")
+                .about("compare two images of the same size, lower is better")
+                .arg(
+                    Arg::with_name("image")
+                        .value_name("IMAGE")
+                        .help("reference image")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("other")
+                        .value_name("OTHER")
+                        .help("image compared against the reference")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("metric")
+                        .long("metric")
+                        .value_name("METRIC")
+                        .help("distance used to compare the images")
+                        .takes_value(true)
+                        .possible_values(&["rmse", "ssim", "ms-ssim", "lab"])
+                        .default_value("rmse"),
+                ),
         )
         .get_matches();
 
-    let img_path = matches
-        .value_of("image")
-        .map(|istr| Path::new(istr))
-        .unwrap();
-
-    let mut options = simulation::Options::default();
-    if matches.is_present("shape") {
-        options.shape = matches.value_of("shape").unwrap().to_string();
-    }
-    if matches.is_present("maxiter") {
-        options.max_iter = matches.value_of("maxiter").unwrap().parse().unwrap();
-    }
-    options.render_debug_rasters = matches.is_present("debug");
+    let result = match matches.subcommand() {
+        ("run", Some(m)) => {
+            let options = options(m);
+            let output = options.output.clone();
+            let debug = options.render_debug_rasters;
+            load_target(m.value_of("image").unwrap())
+                .and_then(|img| Simulation::new(Arc::new(img), options))
+                .and_then(|simulation| simulate(simulation, debug, &output))
+        }
+        ("resume", Some(m)) => {
+            let options = options(m);
+            let output = options.output.clone();
+            let debug = options.render_debug_rasters;
+            load_target(m.value_of("image").unwrap())
+                .and_then(|img| {
+                    let model = Model::load_shapes(Path::new(m.value_of("shapes").unwrap()))?;
+                    Simulation::resume(Arc::new(img), model, options)
+                })
+                .and_then(|simulation| simulate(simulation, debug, &output))
+        }
+        ("batch", Some(m)) => {
+            let options = options(m);
+            batch::run(
+                Path::new(m.value_of("input").unwrap()),
+                Path::new(m.value_of("out").unwrap()),
+                &options,
+                m.is_present("force"),
+            )
+            .map(|report| {
+                println!(
+                    "processed: {}, up to date: {}, failed: {}",
+                    report.processed.len(),
+                    report.skipped.len(),
+                    report.failed.len()
+                );
+                for (input, e) in &report.failed {
+                    println!("  {}: {}", input.display(), e);
+                }
+                if !report.is_success() {
+                    process::exit(1);
+                }
+            })
+        }
+        ("config", Some(m)) => {
+            let options = options(m);
+            if m.is_present("dump") {
+                let format = parse_arg("format", m.value_of("format").unwrap());
+                config::dump(&options, format).map(|dump| print!("{}", dump))
+            } else {
+                Ok(())
+            }
+        }
+        ("render", Some(m)) => {
+            let width = m.value_of("width").map(|w| parse_arg("width", w));
+            geoshaper::render(
+                Path::new(m.value_of("shapes").unwrap()),
+                Path::new(m.value_of("output").unwrap()),
+                width,
+            )
+        }
+        ("score", Some(m)) => geoshaper::score_with_metric(
+            Path::new(m.value_of("image").unwrap()),
+            Path::new(m.value_of("other").unwrap()),
+            parse_arg("metric", m.value_of("metric").unwrap()),
+        )
+        .map(|score| println!("{}", score)),
+        _ => unreachable!(),
+    };
 
-    match geoshaper::run(&img_path, Some(options)) {
+    match result {
         Ok(_) => process::exit(0),
         Err(e) => {
             eprintln!("err: {}", e);
@@ -75,3 +447,179 @@ fn main() {
         }
     }
 }
+
+fn load_target(value: &str) -> Result<DynamicImage, Error> {
+    if value == "-" {
+        let mut buffer = Vec::new();
+        io::stdin().read_to_end(&mut buffer)?;
+        geoshaper::load_image(&buffer)
+    } else {
+        geoshaper::load_image(Path::new(value))
+    }
+}
+
+fn simulate(
+    mut simulation: Simulation,
+    render_debug_rasters: bool,
+    output: &Path,
+) -> Result<(), Error> {
+    if render_debug_rasters {
+        let mut evolutions = 0;
+        simulation.set_observer(move |event, current| {
+            if let Event::Accepted { .. } = *event {
+                let fname = format!("./tmp/evolution-{}.png", evolutions);
+                if let Err(e) = current.save_raster(Path::new(&fname)) {
+                    eprintln!("err: saving {} failed: {}", fname, e);
+                }
+                evolutions += 1;
+            }
+        });
+    }
+    let model = simulation.finish()?;
+    report(&model, output)
+}
+
+fn report(model: &Model, output: &Path) -> Result<(), Error> {
+    model.export(output)?;
+    if let Some(reason) = model.stop_reason {
+        println!("stopped: {}", reason);
+    }
+    if let Some(score) = model.score() {
+        println!("shapes: {}, score: {}", model.polygons.len(), score);
+    }
+    Ok(())
+}
+
+fn options(matches: &ArgMatches) -> simulation::Options {
+    let mut options = match matches.value_of("config") {
+        Some(path) => config::load(Path::new(path)).unwrap_or_else(|e| {
+            eprintln!("err: invalid config: {}", e);
+            process::exit(2);
+        }),
+        None => simulation::Options::default(),
+    };
+    if let Some(output) = matches.value_of("output") {
+        options.output = PathBuf::from(output);
+    }
+    if let Some(shape) = matches.value_of("shape") {
+        options.shape = shape.to_string();
+    }
+    if let Some(stamp) = matches.value_of("stamp") {
+        options.stamp = Some(PathBuf::from(stamp));
+    }
+    if let Some(metric) = matches.value_of("metric") {
+        options.metric = metric.to_string();
+    }
+    if let Some(weights) = matches.value_of("weights") {
+        options.weights = Some(PathBuf::from(weights));
+    }
+    if let Some(auto_weights) = matches.value_of("autoweights") {
+        options.auto_weights = Some(auto_weights.to_string());
+    }
+    if let Some(placement) = matches.value_of("placement") {
+        options.placement = placement.to_string();
+    }
+    if let Some(min_shape_size) = matches.value_of("minshapesize") {
+        options.min_shape_size = Some(min_shape_size.to_string());
+    }
+    if let Some(max_shape_size) = matches.value_of("maxshapesize") {
+        options.max_shape_size = Some(max_shape_size.to_string());
+    }
+    if let Some(max_aspect_ratio) = matches.value_of("maxaspectratio") {
+        options.max_aspect_ratio = Some(parse_arg("max-aspect-ratio", max_aspect_ratio));
+    }
+    if let Some(size_schedule) = matches.value_of("sizeschedule") {
+        options.size_schedule = Some(size_schedule.to_string());
+    }
+    if let Some(background) = matches.value_of("background") {
+        options.background = background.to_string();
+    }
+    if let Some(gradients) = matches.value_of("gradients") {
+        options.gradients = Some(gradients.to_string());
+    }
+    if let Some(style) = matches.value_of("style") {
+        options.style = style.to_string();
+    }
+    if let Some(color_mode) = matches.value_of("colormode") {
+        options.color_mode = color_mode.to_string();
+    }
+    if let Some(inks) = matches.value_of("inks") {
+        options.inks = Some(inks.to_string());
+    }
+    if let Some(palette) = matches.value_of("palette") {
+        options.palette = Some(PathBuf::from(palette));
+    }
+    if let Some(palette_size) = matches.value_of("palettesize") {
+        options.palette_size = Some(parse_arg("palette-size", palette_size));
+    }
+    if let Some(max_iter) = matches.value_of("maxiter") {
+        options.max_iter = parse_arg("maxiter", max_iter);
+    }
+    if let Some(num_genes) = matches.value_of("genes") {
+        options.num_genes = parse_arg("genes", num_genes);
+    }
+    if let Some(alpha) = matches.value_of("alpha") {
+        options.alpha = parse_arg("alpha", alpha);
+    }
+    if let Some(seed) = matches.value_of("seed") {
+        options.seed = Some(parse_arg("seed", seed));
+    }
+    if let Some(target_score) = matches.value_of("targetscore") {
+        options.target_score = Some(parse_arg("target-score", target_score));
+    }
+    if let Some(time_limit) = matches.value_of("timelimit") {
+        options.time_limit = Some(parse_arg("time-limit", time_limit));
+    }
+    if let Some(max_shapes) = matches.value_of("maxshapes") {
+        options.max_shapes = Some(parse_arg("max-shapes", max_shapes));
+    }
+    if let Some(max_stagnation) = matches.value_of("stagnation") {
+        options.max_stagnation = Some(parse_arg("stagnation", max_stagnation));
+    }
+    if let Some(threads) = matches.value_of("threads") {
+        options.threads = parse_arg("threads", threads);
+    }
+    if let Some(max_size) = matches.value_of("maxsize") {
+        options.max_size = Some(parse_arg("max-size", max_size));
+    }
+    if matches.is_present("debug") {
+        options.render_debug_rasters = true;
+    }
+    if matches.is_present("nodebug") {
+        options.render_debug_rasters = false;
+    }
+
+    if let Err(e) = options.validate() {
+        eprintln!("err: invalid options: {}", e);
+        process::exit(2);
+    }
+    options
+}
+
+fn is_positive_integer(value: String) -> Result<(), String> {
+    match value.parse::<u32>() {
+        Ok(v) if v > 0 => Ok(()),
+        _ => Err(format!("`{}` is not a positive integer", value)),
+    }
+}
+
+fn is_integer(value: String) -> Result<(), String> {
+    match value.parse::<u64>() {
+        Ok(_) => Ok(()),
+        _ => Err(format!("`{}` is not an integer", value)),
+    }
+}
+
+fn is_number(value: String) -> Result<(), String> {
+    match value.parse::<f32>() {
+        Ok(_) => Ok(()),
+        _ => Err(format!("`{}` is not a number", value)),
+    }
+}
+
+fn parse_arg<T: FromStr>(name: &str, value: &str) -> T {
+    value.parse().unwrap_or_else(|_| {
+        eprintln!("err: invalid value for --{}: `{}`", name, value);
+        process::exit(2);
+    })
+}
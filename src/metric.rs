@@ -0,0 +1,571 @@
+use image::{DynamicImage, GenericImage, Rgba, RgbaImage};
+use image_utils::{image_area_sse_delta, image_diff, pixel_area};
+use shape::Point;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use weights::{total_weight, weight_at, Weights};
+
+/// side of the square windows SSIM is computed on
+const BLOCK: u32 = 8;
+/// stabilizing constants of SSIM for 8 bit values, (0.01 * 255)^2 and
+/// (0.03 * 255)^2
+const C1: f64 = 6.5025;
+const C2: f64 = 58.5225;
+/// weights of the scales of MS-SSIM, finest first
+const MS_WEIGHTS: [f64; 5] = [0.0448, 0.2856, 0.3001, 0.2363, 0.1333];
+
+/**
+ * A distance between the target and a rendered image, lower is better.
+ *
+ * `score` rates the whole image. `delta` rates a mutation: it returns the
+ * change of the total error when `current` is replaced by `candidate`, which
+ * may only differ from `current` within the pixels of `pixel_area(bounds)`.
+ * A negative delta always means a lower score, the two are not on the same
+ * scale though. The error of every pixel is scaled by its weight, if given.
+ */
+pub trait Metric: Send + Sync {
+    fn score(&self, target: Arc<DynamicImage>, img: &RgbaImage, weights: Option<&Weights>) -> f32;
+    fn delta(
+        &self,
+        target: Arc<DynamicImage>,
+        current: &RgbaImage,
+        candidate: &RgbaImage,
+        bounds: &[Point; 2],
+        weights: Option<&Weights>,
+    ) -> f64;
+}
+
+/// root mean square error of the rgb channels
+pub struct Rmse;
+
+/**
+ * Structural similarity, scored as 1 - SSIM. It only compares the luma
+ * channel, so shapes of the wrong hue but the right brightness are not
+ * penalized, and it is computed on non-overlapping `BLOCK` x `BLOCK` windows
+ * instead of a sliding gaussian one, which keeps the delta of a mutation
+ * local but makes the score depend on where edges fall on the grid.
+ */
+pub struct Ssim;
+
+/**
+ * Multi-scale structural similarity, scored as 1 - MS-SSIM. The images are
+ * halved up to five times, the contrast and structure terms of every scale
+ * and the luminance term of the coarsest one are combined with the weights
+ * of Wang et al. Like `Ssim` it compares the luma channel on `BLOCK` x
+ * `BLOCK` windows.
+ */
+pub struct MsSsim;
+
+/// mean CIE76 color difference (ΔE) in CIELAB space
+pub struct Lab;
+
+impl Metric for Rmse {
+    fn score(&self, target: Arc<DynamicImage>, img: &RgbaImage, weights: Option<&Weights>) -> f32 {
+        image_diff(target, img, weights)
+    }
+
+    fn delta(
+        &self,
+        target: Arc<DynamicImage>,
+        current: &RgbaImage,
+        candidate: &RgbaImage,
+        bounds: &[Point; 2],
+        weights: Option<&Weights>,
+    ) -> f64 {
+        image_area_sse_delta(target, current, candidate, bounds, weights)
+    }
+}
+
+impl Metric for Ssim {
+    fn score(&self, target: Arc<DynamicImage>, img: &RgbaImage, weights: Option<&Weights>) -> f32 {
+        let (width, height) = target.dimensions();
+        let a = Plane::from_target(&target);
+        let b = Plane::from_raster(img);
+        let w = Plane::from_fn(width, height, |x, y| weight_at(weights, x, y));
+        let mut total = 0.0;
+        let mut weight_sum = 0.0;
+        for by in 0..blocks(height) {
+            for bx in 0..blocks(width) {
+                let (l, cs) = block_terms(&a, &b, bx, by);
+                let weight = block_weight(&w, bx, by);
+                total += weight * (1.0 - l * cs);
+                weight_sum += weight;
+            }
+        }
+        if weight_sum <= 0.0 {
+            return 0.0;
+        }
+        (total / weight_sum) as f32
+    }
+
+    /// only the windows overlapping the changed pixels are compared
+    fn delta(
+        &self,
+        target: Arc<DynamicImage>,
+        current: &RgbaImage,
+        candidate: &RgbaImage,
+        bounds: &[Point; 2],
+        weights: Option<&Weights>,
+    ) -> f64 {
+        let (width, height) = target.dimensions();
+        let (min_x, min_y, max_x, max_y) = pixel_area(bounds, width, height);
+        if min_x >= max_x || min_y >= max_y {
+            return 0.0;
+        }
+        // planes covering just the affected windows
+        let x0 = min_x / BLOCK * BLOCK;
+        let y0 = min_y / BLOCK * BLOCK;
+        let x1 = ((max_x - 1) / BLOCK * BLOCK + BLOCK).min(width);
+        let y1 = ((max_y - 1) / BLOCK * BLOCK + BLOCK).min(height);
+        let crop = |f: &dyn Fn(u32, u32) -> f64| {
+            Plane::from_fn(x1 - x0, y1 - y0, |x, y| f(x0 + x, y0 + y))
+        };
+        let a = crop(&|x, y| luma(&target.get_pixel(x, y)));
+        let current = crop(&|x, y| luma(current.get_pixel(x, y)));
+        let candidate = crop(&|x, y| luma(candidate.get_pixel(x, y)));
+        let w = crop(&|x, y| weight_at(weights, x, y));
+
+        let mut delta = 0.0;
+        for by in 0..blocks(a.height) {
+            for bx in 0..blocks(a.width) {
+                let (l, cs) = block_terms(&a, &current, bx, by);
+                let (l2, cs2) = block_terms(&a, &candidate, bx, by);
+                delta += block_weight(&w, bx, by) * (l * cs - l2 * cs2);
+            }
+        }
+        delta
+    }
+}
+
+impl Metric for MsSsim {
+    fn score(&self, target: Arc<DynamicImage>, img: &RgbaImage, weights: Option<&Weights>) -> f32 {
+        let (width, height) = target.dimensions();
+        let mut a = Plane::from_target(&target);
+        let mut b = Plane::from_raster(img);
+        let mut w = Plane::from_fn(width, height, |x, y| weight_at(weights, x, y));
+        let mut result = 1.0;
+        let mut scale_weights = 0.0;
+        for (i, scale_weight) in MS_WEIGHTS.iter().enumerate() {
+            let (mut l, mut cs) = (0.0, 0.0);
+            let mut weight_sum = 0.0;
+            for by in 0..blocks(a.height) {
+                for bx in 0..blocks(a.width) {
+                    let terms = block_terms(&a, &b, bx, by);
+                    let weight = block_weight(&w, bx, by);
+                    l += weight * terms.0;
+                    cs += weight * terms.1;
+                    weight_sum += weight;
+                }
+            }
+            if weight_sum <= 0.0 {
+                return 0.0;
+            }
+            let (l, cs) = (l / weight_sum, cs / weight_sum);
+
+            let last = i + 1 == MS_WEIGHTS.len() || a.width < 2 * BLOCK || a.height < 2 * BLOCK;
+            let term = if last { l * cs } else { cs };
+            result *= term.max(0.0).powf(*scale_weight);
+            scale_weights += scale_weight;
+            if last {
+                break;
+            }
+            a = a.downsample();
+            b = b.downsample();
+            w = w.downsample();
+        }
+        // small images use fewer scales, renormalize their weights
+        (1.0 - result.powf(1.0 / scale_weights)) as f32
+    }
+
+    /// the scales mix neighbouring pixels, the delta is the change of the
+    /// score of the whole image
+    fn delta(
+        &self,
+        target: Arc<DynamicImage>,
+        current: &RgbaImage,
+        candidate: &RgbaImage,
+        _bounds: &[Point; 2],
+        weights: Option<&Weights>,
+    ) -> f64 {
+        let after = self.score(target.clone(), candidate, weights);
+        (after - self.score(target, current, weights)) as f64
+    }
+}
+
+impl Metric for Lab {
+    fn score(&self, target: Arc<DynamicImage>, img: &RgbaImage, weights: Option<&Weights>) -> f32 {
+        let (width, height) = target.dimensions();
+        let weight_sum = total_weight(weights, width, height);
+        if weight_sum <= 0.0 {
+            return 0.0;
+        }
+        let total: f64 = target
+            .pixels()
+            .map(|(x, y, pixel)| delta_e(&pixel, img.get_pixel(x, y)) * weight_at(weights, x, y))
+            .sum();
+        (total / weight_sum) as f32
+    }
+
+    fn delta(
+        &self,
+        target: Arc<DynamicImage>,
+        current: &RgbaImage,
+        candidate: &RgbaImage,
+        bounds: &[Point; 2],
+        weights: Option<&Weights>,
+    ) -> f64 {
+        let (width, height) = target.dimensions();
+        let (min_x, min_y, max_x, max_y) = pixel_area(bounds, width, height);
+        let mut delta = 0.0;
+        for x in min_x..max_x {
+            for y in min_y..max_y {
+                let expected = target.get_pixel(x, y);
+                let change = delta_e(&expected, candidate.get_pixel(x, y))
+                    - delta_e(&expected, current.get_pixel(x, y));
+                delta += change * weight_at(weights, x, y);
+            }
+        }
+        delta
+    }
+}
+
+/// the metrics that can be selected with `Options::metric`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Metrics {
+    #[default]
+    Rmse,
+    Ssim,
+    MsSsim,
+    Lab,
+}
+
+impl Metrics {
+    pub fn get(&self) -> &'static dyn Metric {
+        match *self {
+            Metrics::Rmse => &Rmse,
+            Metrics::Ssim => &Ssim,
+            Metrics::MsSsim => &MsSsim,
+            Metrics::Lab => &Lab,
+        }
+    }
+}
+
+impl FromStr for Metrics {
+    type Err = String;
+
+    fn from_str(s: &str) -> ::std::result::Result<Metrics, String> {
+        match s {
+            "rmse" => Ok(Metrics::Rmse),
+            "ssim" => Ok(Metrics::Ssim),
+            "ms-ssim" => Ok(Metrics::MsSsim),
+            "lab" => Ok(Metrics::Lab),
+            _ => Err(format!("unknown metric: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Metrics::Rmse => write!(f, "rmse"),
+            Metrics::Ssim => write!(f, "ssim"),
+            Metrics::MsSsim => write!(f, "ms-ssim"),
+            Metrics::Lab => write!(f, "lab"),
+        }
+    }
+}
+
+/// a single channel image with floating point values
+struct Plane {
+    width: u32,
+    height: u32,
+    data: Vec<f64>,
+}
+
+impl Plane {
+    fn from_fn<F: Fn(u32, u32) -> f64>(width: u32, height: u32, f: F) -> Plane {
+        let mut data = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                data.push(f(x, y));
+            }
+        }
+        Plane {
+            width: width,
+            height: height,
+            data: data,
+        }
+    }
+
+    fn from_target(img: &DynamicImage) -> Plane {
+        let (width, height) = img.dimensions();
+        Plane::from_fn(width, height, |x, y| luma(&img.get_pixel(x, y)))
+    }
+
+    fn from_raster(img: &RgbaImage) -> Plane {
+        let (width, height) = img.dimensions();
+        Plane::from_fn(width, height, |x, y| luma(img.get_pixel(x, y)))
+    }
+
+    fn get(&self, x: u32, y: u32) -> f64 {
+        self.data[(y * self.width + x) as usize]
+    }
+
+    /// halves the size by averaging 2x2 pixels
+    fn downsample(&self) -> Plane {
+        Plane::from_fn((self.width / 2).max(1), (self.height / 2).max(1), |x, y| {
+            let (x, y) = (2 * x, 2 * y);
+            let x1 = (x + 1).min(self.width - 1);
+            let y1 = (y + 1).min(self.height - 1);
+            (self.get(x, y) + self.get(x1, y) + self.get(x, y1) + self.get(x1, y1)) / 4.0
+        })
+    }
+}
+
+fn blocks(size: u32) -> u32 {
+    size.div_ceil(BLOCK)
+}
+
+/**
+ * @brief      luminance and contrast-structure terms of SSIM for the window
+ *             (bx, by), windows at the right and bottom edge may be smaller
+ */
+fn block_terms(a: &Plane, b: &Plane, bx: u32, by: u32) -> (f64, f64) {
+    let (x0, y0) = (bx * BLOCK, by * BLOCK);
+    let x1 = (x0 + BLOCK).min(a.width);
+    let y1 = (y0 + BLOCK).min(a.height);
+    let n = ((x1 - x0) * (y1 - y0)) as f64;
+
+    let (mut sum_a, mut sum_b) = (0.0, 0.0);
+    for y in y0..y1 {
+        for x in x0..x1 {
+            sum_a += a.get(x, y);
+            sum_b += b.get(x, y);
+        }
+    }
+    let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+
+    let (mut var_a, mut var_b, mut cov) = (0.0, 0.0, 0.0);
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let da = a.get(x, y) - mean_a;
+            let db = b.get(x, y) - mean_b;
+            var_a += da * da;
+            var_b += db * db;
+            cov += da * db;
+        }
+    }
+    let (var_a, var_b, cov) = (var_a / n, var_b / n, cov / n);
+
+    let l = (2.0 * mean_a * mean_b + C1) / (mean_a * mean_a + mean_b * mean_b + C1);
+    let cs = (2.0 * cov + C2) / (var_a + var_b + C2);
+    (l, cs)
+}
+
+/// mean of the weights in the window (bx, by)
+fn block_weight(w: &Plane, bx: u32, by: u32) -> f64 {
+    let (x0, y0) = (bx * BLOCK, by * BLOCK);
+    let x1 = (x0 + BLOCK).min(w.width);
+    let y1 = (y0 + BLOCK).min(w.height);
+    let mut sum = 0.0;
+    for y in y0..y1 {
+        for x in x0..x1 {
+            sum += w.get(x, y);
+        }
+    }
+    sum / ((x1 - x0) * (y1 - y0)) as f64
+}
+
+/// luma premultiplied by the alpha
+fn luma(p: &Rgba<u8>) -> f64 {
+    let luma = 0.299 * p.data[0] as f64 + 0.587 * p.data[1] as f64 + 0.114 * p.data[2] as f64;
+    luma * p.data[3] as f64 / 255.0
+}
+
+/// converts a premultiplied sRGB color to CIELAB with a D65 white point
+fn to_lab(p: &Rgba<u8>) -> [f64; 3] {
+    let alpha = p.data[3] as f64 / 255.0;
+    let linear = |c: u8| {
+        let c = c as f64 / 255.0 * alpha;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let (r, g, b) = (linear(p.data[0]), linear(p.data[1]), linear(p.data[2]));
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+
+    let f = |t: f64| {
+        if t > 0.008856 {
+            t.cbrt()
+        } else {
+            7.787 * t + 16.0 / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// CIE76 color difference, a difference in alpha counts like one in
+/// lightness
+fn delta_e(p1: &Rgba<u8>, p2: &Rgba<u8>) -> f64 {
+    let (lab1, lab2) = (to_lab(p1), to_lab(p2));
+    let dl = lab1[0] - lab2[0];
+    let da = lab1[1] - lab2[1];
+    let db = lab1[2] - lab2[2];
+    let dalpha = (p1.data[3] as f64 - p2.data[3] as f64) * 100.0 / 255.0;
+    (dl * dl + da * da + db * db + dalpha * dalpha).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METRICS: [Metrics; 4] = [Metrics::Rmse, Metrics::Ssim, Metrics::MsSsim, Metrics::Lab];
+
+    fn get_test_image() -> Arc<DynamicImage> {
+        Arc::new(DynamicImage::ImageRgba8(RgbaImage::from_fn(
+            64,
+            48,
+            |x, y| Rgba {
+                data: [(x * 4) as u8, (y * 5) as u8, ((x + y) * 2) as u8, 255],
+            },
+        )))
+    }
+
+    fn fill(img: &mut RgbaImage, bounds: &[Point; 2], color: [u8; 4]) {
+        for x in bounds[0].x as u32..bounds[1].x as u32 {
+            for y in bounds[0].y as u32..bounds[1].y as u32 {
+                img.put_pixel(x, y, Rgba { data: color });
+            }
+        }
+    }
+
+    #[test]
+    fn should_parse_metrics() {
+        for metric in METRICS.iter() {
+            assert_eq!(metric.to_string().parse::<Metrics>(), Ok(*metric));
+        }
+        assert!("psnr".parse::<Metrics>().is_err());
+    }
+
+    #[test]
+    fn identical_images_should_score_zero() {
+        let target = get_test_image();
+        for metric in METRICS.iter() {
+            let score = metric.get().score(target.clone(), &target.to_rgba(), None);
+            assert!(score.abs() < 1e-6, "{}: {}", metric, score);
+        }
+    }
+
+    #[test]
+    fn should_ignore_errors_without_weight() {
+        let target = get_test_image();
+        let damage = [Point { x: 0.0, y: 0.0 }, Point { x: 16.0, y: 16.0 }];
+        let mut img = target.to_rgba();
+        fill(&mut img, &damage, [255, 0, 0, 255]);
+        let weights = Weights::from_data(
+            64,
+            48,
+            (0..64 * 48)
+                .map(|i| if i % 64 < 16 && i / 64 < 16 { 0.0 } else { 1.0 })
+                .collect(),
+        );
+        for metric in METRICS.iter() {
+            let unweighted = metric.get().score(target.clone(), &img, None);
+            let score = metric.get().score(target.clone(), &img, Some(&weights));
+            assert!(score < unweighted, "{}", metric);
+            // the coarse windows of ms-ssim reach into weighted pixels
+            if *metric != Metrics::MsSsim {
+                assert!(score.abs() < 1e-6, "{}: {}", metric, score);
+            }
+        }
+    }
+
+    #[test]
+    fn ms_ssim_should_agree_with_ssim() {
+        // a single window at a single scale is plain SSIM
+        let small = Arc::new(DynamicImage::ImageRgba8(RgbaImage::from_fn(
+            8,
+            8,
+            |x, y| Rgba {
+                data: [(x * 30) as u8, (y * 30) as u8, 0, 255],
+            },
+        )));
+        let mut img = small.to_rgba();
+        fill(
+            &mut img,
+            &[Point { x: 0.0, y: 0.0 }, Point { x: 4.0, y: 8.0 }],
+            [90, 90, 90, 255],
+        );
+        let ssim = Ssim.score(small.clone(), &img, None);
+        assert!(ssim > 0.0);
+        assert!((MsSsim.score(small, &img, None) - ssim).abs() < 1e-6);
+
+        // both rank a larger damage worse
+        let target = get_test_image();
+        let mut light = target.to_rgba();
+        fill(
+            &mut light,
+            &[Point { x: 0.0, y: 0.0 }, Point { x: 16.0, y: 16.0 }],
+            [255, 0, 0, 255],
+        );
+        let mut heavy = light.clone();
+        fill(
+            &mut heavy,
+            &[Point { x: 16.0, y: 16.0 }, Point { x: 48.0, y: 40.0 }],
+            [0, 255, 0, 255],
+        );
+        for metric in [&Ssim as &dyn Metric, &MsSsim].iter() {
+            let light = metric.score(target.clone(), &light, None);
+            let heavy = metric.score(target.clone(), &heavy, None);
+            assert!(0.0 < light && light < heavy);
+        }
+    }
+
+    #[test]
+    fn delta_should_follow_the_score() {
+        let target = get_test_image();
+        let mut current = target.to_rgba();
+        fill(
+            &mut current,
+            &[Point { x: 0.0, y: 0.0 }, Point { x: 32.0, y: 32.0 }],
+            [255, 0, 0, 255],
+        );
+        // repairing whole windows of the damage
+        let bounds = [Point { x: 8.0, y: 8.0 }, Point { x: 24.0, y: 24.0 }];
+        let mut candidate = current.clone();
+        for x in 8..24 {
+            for y in 8..24 {
+                candidate.put_pixel(x, y, target.get_pixel(x, y));
+            }
+        }
+
+        for metric in METRICS.iter() {
+            let delta = metric
+                .get()
+                .delta(target.clone(), &current, &candidate, &bounds, None);
+            let before = metric.get().score(target.clone(), &current, None);
+            let after = metric.get().score(target.clone(), &candidate, None);
+            assert_eq!(delta < 0.0, after < before, "{}", metric);
+            // the coarse scales of ms-ssim still see the damaged edges
+            if *metric != Metrics::MsSsim {
+                assert!(delta < 0.0, "{}", metric);
+            }
+        }
+
+        // for the windowed metrics the delta is the exact change of the total
+        let lab = Lab.delta(target.clone(), &current, &candidate, &bounds, None);
+        let lab_change =
+            Lab.score(target.clone(), &candidate, None) - Lab.score(target.clone(), &current, None);
+        assert!((lab / (64.0 * 48.0) - lab_change as f64).abs() < 1e-4);
+        let ssim = Ssim.delta(target.clone(), &current, &candidate, &bounds, None);
+        let ssim_change = Ssim.score(target.clone(), &candidate, None)
+            - Ssim.score(target.clone(), &current, None);
+        assert!((ssim / (8.0 * 6.0) - ssim_change as f64).abs() < 1e-4);
+    }
+}
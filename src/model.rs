@@ -0,0 +1,593 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use error::{Error, Result};
+use image::{Rgba, RgbaImage};
+use image_utils::rgba_to_str;
+use nsvg;
+use shape::{Gradient, Point, Polygon, Shapes, Stroke};
+use simulation::StopReason;
+use stamp::Stamp;
+use std::sync::Arc;
+
+const SHAPES_HEADER: &str = "geoshaper-shapes 2";
+/// header of the files written before stamps, which are still read
+const SHAPES_HEADER_V1: &str = "geoshaper-shapes 1";
+
+/**
+ * The result of a simulation: the canvas size, the background color and the
+ * list of accepted polygons. It does not need the target image, so it can be
+ * rendered or resumed later on from a shape file.
+ *
+ * Shape file format (one record per line):
+ *
+ * geoshaper-shapes 2
+ * size <width> <height>
+ * background <r> <g> <b> <a>
+ * scores <score> <score> ...
+ * stamp-path <x0> <y0> <x1> <y1> <path>
+ * <shape> <r> <g> <b> <a> [<gradient>] [<stroke>] <x>,<y> <x>,<y> ...
+ *
+ * where the optional gradient blends the fill color into a second color:
+ * `linear:<angle>:<r>,<g>,<b>,<a>` or `radial:<r>,<g>,<b>,<a>`, and the
+ * optional stroke outlines the polygon: `stroke:<width>:<r>,<g>,<b>,<a>`.
+ * The `stamp-path` record holds the area the path of `stamp` polygons covers and
+ * its `d` attribute; it precedes them. Version 1 files are the same without
+ * stamps.
+ */
+#[derive(Debug, Clone)]
+pub struct Model {
+    pub width: u32,
+    pub height: u32,
+    pub background: Rgba<u8>,
+    pub polygons: Vec<Polygon>,
+    /// score of the whole image before the first and after every accepted
+    /// polygon, lower is better
+    pub scores: Vec<f32>,
+    /// the condition that ended the simulation which produced this model
+    pub stop_reason: Option<StopReason>,
+    /// size of the original target the result is exported at, differs from
+    /// the model size when the simulation ran on a downscaled copy
+    pub target_size: (u32, u32),
+}
+
+impl Model {
+    pub fn new(width: u32, height: u32, background: Rgba<u8>, polygons: Vec<Polygon>) -> Model {
+        Model {
+            width: width,
+            height: height,
+            background: background,
+            polygons: polygons,
+            scores: Vec::new(),
+            stop_reason: None,
+            target_size: (width, height),
+        }
+    }
+
+    /// score of the whole image, `None` until the model has been scored
+    pub fn score(&self) -> Option<f32> {
+        self.scores.last().cloned()
+    }
+
+    pub fn load_shapes(path: &Path) -> Result<Model> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut lines = reader.lines();
+
+        match lines.next() {
+            Some(line) => {
+                let line = line?;
+                if line.trim() != SHAPES_HEADER && line.trim() != SHAPES_HEADER_V1 {
+                    return Err(shape_file_error(path, "not a geoshaper shape file"));
+                }
+            }
+            None => return Err(shape_file_error(path, "empty shape file")),
+        }
+
+        let mut size: Option<(u32, u32)> = None;
+        let mut background: Option<Rgba<u8>> = None;
+        let mut scores: Vec<f32> = Vec::new();
+        let mut polygons: Vec<Polygon> = Vec::new();
+        let mut stamp: Option<Arc<Stamp>> = None;
+        for (n, line) in lines.enumerate() {
+            let line = line?;
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.is_empty() {
+                continue;
+            }
+            // header is line 1
+            let lineno = n + 2;
+            match fields[0] {
+                "size" => {
+                    if fields.len() != 3 {
+                        return Err(line_error(lineno, "expected `size <width> <height>`"));
+                    }
+                    size = Some((
+                        parse_field(fields[1], lineno)?,
+                        parse_field(fields[2], lineno)?,
+                    ));
+                }
+                "background" => {
+                    background = Some(parse_color(&fields[1..], lineno)?);
+                }
+                "stamp-path" => {
+                    if fields.len() < 6 {
+                        return Err(line_error(
+                            lineno,
+                            "expected `stamp-path <x0> <y0> <x1> <y1> <path>`",
+                        ));
+                    }
+                    let bounds = [
+                        Point {
+                            x: parse_field(fields[1], lineno)?,
+                            y: parse_field(fields[2], lineno)?,
+                        },
+                        Point {
+                            x: parse_field(fields[3], lineno)?,
+                            y: parse_field(fields[4], lineno)?,
+                        },
+                    ];
+                    stamp = Some(Arc::new(Stamp::new(fields[5..].join(" "), bounds)));
+                }
+                "scores" => {
+                    for score in &fields[1..] {
+                        scores.push(parse_field(score, lineno)?);
+                    }
+                }
+                shape => {
+                    let (width, height) = size
+                        .ok_or_else(|| line_error(lineno, "polygon found before `size` record"))?;
+                    let shape: Shapes =
+                        shape.parse().map_err(|e: String| line_error(lineno, &e))?;
+                    if fields.len() < 6 {
+                        return Err(line_error(lineno, "polygon without points"));
+                    }
+                    let fill_color = parse_color(&fields[1..5], lineno)?;
+                    let (mut gradient, mut stroke) = (None, None);
+                    let mut first_point = 5;
+                    for field in fields[5..].iter().take_while(|f| f.contains(':')) {
+                        if field.starts_with("stroke:") {
+                            stroke = Some(parse_stroke(field, lineno)?);
+                        } else {
+                            gradient = Some(parse_gradient(field, lineno)?);
+                        }
+                        first_point += 1;
+                    }
+                    if fields.len() <= first_point {
+                        return Err(line_error(lineno, "polygon without points"));
+                    }
+                    let mut points: Vec<Point> = Vec::new();
+                    for point in &fields[first_point..] {
+                        points.push(parse_point(point, lineno)?);
+                    }
+                    let mut polygon = Polygon::from_points(
+                        shape,
+                        points,
+                        width as f32,
+                        height as f32,
+                        fill_color,
+                    );
+                    polygon.set_gradient(gradient);
+                    polygon.set_stroke(stroke);
+                    if shape == Shapes::Stamp {
+                        let stamp = stamp.clone().ok_or_else(|| {
+                            line_error(lineno, "stamp polygon found before `stamp-path` record")
+                        })?;
+                        polygon.set_stamp(Some(stamp));
+                    }
+                    polygons.push(polygon);
+                }
+            }
+        }
+
+        let (width, height) =
+            size.ok_or_else(|| shape_file_error(path, "missing `size` record"))?;
+        let background = background.unwrap_or(Rgba {
+            data: [255, 255, 255, 255],
+        });
+        let mut model = Model::new(width, height, background, polygons);
+        model.scores = scores;
+        Ok(model)
+    }
+
+    pub fn save_shapes(&self, path: &Path) -> Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        writeln!(w, "{}", SHAPES_HEADER)?;
+        writeln!(w, "size {} {}", self.width, self.height)?;
+        writeln!(w, "background {}", color_fields(&self.background))?;
+        if !self.scores.is_empty() {
+            write!(w, "scores")?;
+            for score in &self.scores {
+                write!(w, " {}", score)?;
+            }
+            writeln!(w)?;
+        }
+        if let Some(stamp) = self.polygons.iter().filter_map(|p| p.stamp()).next() {
+            let [ref min, ref max] = *stamp.bounds();
+            writeln!(
+                w,
+                "stamp-path {} {} {} {} {}",
+                min.x,
+                min.y,
+                max.x,
+                max.y,
+                stamp.path()
+            )?;
+        }
+        for polygon in &self.polygons {
+            write!(
+                w,
+                "{} {}",
+                polygon.shape(),
+                color_fields(&polygon.fill_color())
+            )?;
+            if let Some(gradient) = polygon.gradient() {
+                write!(w, " {}", gradient_field(&gradient))?;
+            }
+            if let Some(stroke) = polygon.stroke() {
+                write!(w, " {}", stroke_field(&stroke))?;
+            }
+            for point in &polygon.points {
+                write!(w, " {},{}", point.x, point.y)?;
+            }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+
+    /// the size of the model, the size polygon coordinates refer to
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// the svg document of the model stretched to `size`
+    pub fn to_svg(&self, size: (u32, u32)) -> String {
+        svg_document(
+            self.width,
+            self.height,
+            &self.background,
+            &self.polygons,
+            size,
+            false,
+        )
+    }
+
+    /// the model rasterized at `size`
+    pub fn to_rgba_image(&self, size: (u32, u32)) -> Result<RgbaImage> {
+        rasterize(&svg_document(
+            self.width,
+            self.height,
+            &self.background,
+            &self.polygons,
+            size,
+            true,
+        ))
+    }
+
+    /**
+     * @brief      renders the model to `path`, the format is picked from the
+     *             extension (`.svg` or any raster format supported by `image`)
+     */
+    pub fn save(&self, path: &Path, size: (u32, u32)) -> Result<()> {
+        let is_svg = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase() == "svg")
+            .unwrap_or(false);
+        if is_svg {
+            let mut f = File::create(path)?;
+            f.write_all(self.to_svg(size).as_bytes())?;
+        } else {
+            self.to_rgba_image(size)?.save(path)?;
+        }
+        Ok(())
+    }
+
+    /**
+     * @brief      renders the model at the size of the original target and
+     *             writes the shape file next to it
+     */
+    pub fn export(&self, path: &Path) -> Result<()> {
+        self.save(path, self.target_size)?;
+        info!("result saved to {}", path.display());
+        let shapes_path = path.with_extension("shapes");
+        self.save_shapes(&shapes_path)?;
+        info!("shapes saved to {}", shapes_path.display());
+        Ok(())
+    }
+}
+
+/**
+ * @brief      builds the svg document for a list of polygons
+ *
+ * @param      size           width and height of the output, the polygon
+ *                            coordinates are kept and stretched through the
+ *                            viewBox
+ * @param      inline_stamps  draws the path of every stamp in place, nsvg
+ *                            does not follow `<use>` references to a single
+ *                            definition
+ *
+ * @return     String
+ */
+pub fn svg_document(
+    width: u32,
+    height: u32,
+    background: &Rgba<u8>,
+    polygons: &[Polygon],
+    size: (u32, u32),
+    inline_stamps: bool,
+) -> String {
+    let stamp = polygons.iter().filter_map(|polygon| polygon.stamp()).next();
+    let mut svg = format!(
+        "<svg width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\" preserveAspectRatio=\"none\" version=\"1.1\" xmlns=\"http://www.w3.org/2000/svg\"{}>",
+        size.0,
+        size.1,
+        width,
+        height,
+        if stamp.is_some() && !inline_stamps {
+            " xmlns:xlink=\"http://www.w3.org/1999/xlink\""
+        } else {
+            ""
+        }
+    );
+    // a transparent background is left out, the canvas stays empty
+    if background.data[3] > 0 {
+        svg.push_str(&format!(
+            "<rect width=\"{}\" height=\"{}\" x=\"0\" y=\"0\" fill=\"rgb({})\" fill-opacity=\"{}\"/>",
+            width,
+            height,
+            rgba_to_str(background),
+            background.data[3] as f32 / 255.0
+        ));
+    }
+    match stamp {
+        Some(stamp) if !inline_stamps => {
+            svg.push_str(&stamp.svg_definition());
+            for (id, polygon) in polygons.iter().enumerate() {
+                svg.push_str(polygon.svg_use(id).as_str());
+            }
+        }
+        _ => {
+            for (id, polygon) in polygons.iter().enumerate() {
+                svg.push_str(polygon.svg(id).as_str());
+            }
+        }
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+pub fn rasterize(svg: &str) -> Result<RgbaImage> {
+    let svg = nsvg::parse_str(svg, nsvg::Units::Pixel, 96.0)?;
+    Ok(svg.rasterize(1.0)?)
+}
+
+fn shape_file_error(path: &Path, msg: &str) -> Error {
+    Error::InvalidShapeFile(format!("{}: {}", path.display(), msg))
+}
+
+fn line_error(lineno: usize, msg: &str) -> Error {
+    Error::InvalidShapeFile(format!("line {}: {}", lineno, msg))
+}
+
+fn color_fields(color: &Rgba<u8>) -> String {
+    format!(
+        "{} {} {} {}",
+        color.data[0], color.data[1], color.data[2], color.data[3]
+    )
+}
+
+fn gradient_field(gradient: &Gradient) -> String {
+    let to = color_token(&gradient.to());
+    match *gradient {
+        Gradient::Linear { angle, .. } => format!("linear:{}:{}", angle, to),
+        Gradient::Radial { .. } => format!("radial:{}", to),
+    }
+}
+
+fn stroke_field(stroke: &Stroke) -> String {
+    format!("stroke:{}:{}", stroke.width, color_token(&stroke.color))
+}
+
+/// a color inside of a gradient or stroke field, `<r>,<g>,<b>,<a>`
+fn color_token(color: &Rgba<u8>) -> String {
+    format!(
+        "{},{},{},{}",
+        color.data[0], color.data[1], color.data[2], color.data[3]
+    )
+}
+
+fn parse_color_token(token: &str, lineno: usize) -> Result<Rgba<u8>> {
+    let channels: Vec<&str> = token.split(',').collect();
+    parse_color(&channels, lineno)
+}
+
+fn parse_gradient(field: &str, lineno: usize) -> Result<Gradient> {
+    let parts: Vec<&str> = field.split(':').collect();
+    match parts.as_slice() {
+        ["linear", angle, color] => Ok(Gradient::Linear {
+            angle: parse_field(angle, lineno)?,
+            to: parse_color_token(color, lineno)?,
+        }),
+        ["radial", color] => Ok(Gradient::Radial {
+            to: parse_color_token(color, lineno)?,
+        }),
+        _ => Err(line_error(lineno, &format!("invalid gradient `{}`", field))),
+    }
+}
+
+fn parse_stroke(field: &str, lineno: usize) -> Result<Stroke> {
+    let parts: Vec<&str> = field.split(':').collect();
+    match parts.as_slice() {
+        ["stroke", width, color] => Ok(Stroke {
+            width: parse_field(width, lineno)?,
+            color: parse_color_token(color, lineno)?,
+        }),
+        _ => Err(line_error(lineno, &format!("invalid stroke `{}`", field))),
+    }
+}
+
+fn parse_field<T: ::std::str::FromStr>(field: &str, lineno: usize) -> Result<T> {
+    field
+        .parse()
+        .map_err(|_| line_error(lineno, &format!("invalid value `{}`", field)))
+}
+
+fn parse_color(fields: &[&str], lineno: usize) -> Result<Rgba<u8>> {
+    if fields.len() != 4 {
+        return Err(line_error(lineno, "expected a color as `<r> <g> <b> <a>`"));
+    }
+    let mut data = [0u8; 4];
+    for (i, field) in fields.iter().enumerate() {
+        data[i] = parse_field(field, lineno)?;
+    }
+    Ok(Rgba { data: data })
+}
+
+fn parse_point(field: &str, lineno: usize) -> Result<Point> {
+    let mut coords = field.splitn(2, ',');
+    match (coords.next(), coords.next()) {
+        (Some(x), Some(y)) => Ok(Point {
+            x: parse_field(x, lineno)?,
+            y: parse_field(y, lineno)?,
+        }),
+        _ => Err(line_error(lineno, &format!("invalid point `{}`", field))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::thread_rng;
+    use std::env;
+    use std::fs::{self, remove_file};
+
+    #[test]
+    fn should_roundtrip_shape_file() {
+        let polygon = Polygon::new(&mut thread_rng(), Shapes::Rectangle, 64.0, 32.0);
+        let mut shaded = Polygon::new(&mut thread_rng(), Shapes::Triangle, 64.0, 32.0);
+        shaded.set_gradient(Some(Gradient::Linear {
+            angle: 22.5,
+            to: Rgba { data: [1, 2, 3, 4] },
+        }));
+        shaded.set_stroke(Some(Stroke {
+            width: 2.5,
+            color: Rgba { data: [5, 6, 7, 8] },
+        }));
+        let mut model = Model::new(
+            64,
+            32,
+            Rgba {
+                data: [10, 20, 30, 255],
+            },
+            vec![polygon.clone(), shaded.clone()],
+        );
+        model.scores = vec![20.5, 18.25];
+        let path = env::temp_dir().join("geoshaper-roundtrip.shapes");
+        model.save_shapes(&path).unwrap();
+        let loaded = Model::load_shapes(&path).unwrap();
+        remove_file(&path).unwrap();
+
+        assert_eq!(loaded.width, 64);
+        assert_eq!(loaded.height, 32);
+        assert_eq!(loaded.background, model.background);
+        assert_eq!(loaded.polygons.len(), 2);
+        assert_eq!(loaded.polygons[0].points, polygon.points);
+        assert_eq!(loaded.polygons[0].shape(), Shapes::Rectangle);
+        assert_eq!(loaded.polygons[0].gradient(), None);
+        assert_eq!(loaded.polygons[1].points, shaded.points);
+        assert_eq!(loaded.polygons[1].gradient(), shaded.gradient());
+        assert_eq!(loaded.polygons[0].stroke(), None);
+        assert_eq!(loaded.polygons[1].stroke(), shaded.stroke());
+        assert_eq!(loaded.scores, model.scores);
+        assert_eq!(loaded.score(), Some(18.25));
+    }
+
+    #[test]
+    fn should_read_version_1_shape_files() {
+        let path = env::temp_dir().join("geoshaper-version-1.shapes");
+        fs::write(
+            &path,
+            "geoshaper-shapes 1\nsize 64 32\nrectangle 1 2 3 4 0,0 8,0 8,8 0,8\n",
+        )
+        .unwrap();
+        let loaded = Model::load_shapes(&path).unwrap();
+        remove_file(&path).unwrap();
+        assert_eq!((loaded.width, loaded.height), (64, 32));
+        assert_eq!(loaded.polygons.len(), 1);
+        assert_eq!(loaded.polygons[0].shape(), Shapes::Rectangle);
+    }
+
+    #[test]
+    fn should_scale_svg_document() {
+        let model = Model::new(
+            64,
+            32,
+            Rgba {
+                data: [0, 0, 0, 255],
+            },
+            Vec::new(),
+        );
+        let svg = model.to_svg((128, 64));
+        assert!(svg.contains("width=\"128\" height=\"64\" viewBox=\"0 0 64 32\""));
+        // rounded sizes stretch the polygons rather than leave a margin
+        let svg = model.to_svg((404, 203));
+        assert!(svg.contains("width=\"404\" height=\"203\""));
+        assert!(svg.contains("preserveAspectRatio=\"none\""));
+    }
+
+    #[test]
+    fn should_leave_out_transparent_background() {
+        let mut model = Model::new(
+            64,
+            32,
+            Rgba {
+                data: [255, 0, 0, 255],
+            },
+            Vec::new(),
+        );
+        assert!(model
+            .to_svg(model.size())
+            .contains("fill=\"rgb(255,0,0)\" fill-opacity=\"1\""));
+        model.background = Rgba { data: [0, 0, 0, 0] };
+        assert!(!model.to_svg(model.size()).contains("<rect"));
+    }
+
+    #[test]
+    fn should_refer_to_stamps() {
+        let stamp = Arc::new(Stamp::new(
+            "M0 0 H10 V20 Z".to_owned(),
+            [Point { x: 0.0, y: 0.0 }, Point { x: 10.0, y: 20.0 }],
+        ));
+        let polygons: Vec<Polygon> = (0..3)
+            .map(|_| Polygon::generate_stamp(&mut thread_rng(), stamp.clone(), 64.0, 64.0, None))
+            .collect();
+        let model = Model::new(
+            64,
+            64,
+            Rgba {
+                data: [0, 0, 0, 255],
+            },
+            polygons,
+        );
+        let svg = model.to_svg(model.size());
+        assert!(svg.contains("xmlns:xlink=\"http://www.w3.org/1999/xlink\""));
+        assert_eq!(
+            svg.matches("<path id=\"stamp\" d=\"M0 0 H10 V20 Z\"/>")
+                .count(),
+            1
+        );
+        assert_eq!(svg.matches("<use xlink:href=\"#stamp\"").count(), 3);
+        let raster = svg_document(64, 64, &model.background, &model.polygons, (64, 64), true);
+        assert!(!raster.contains("<use"));
+        assert_eq!(raster.matches("<path d=\"M0 0 H10 V20 Z\"").count(), 3);
+
+        let path = env::temp_dir().join("geoshaper-stamps.shapes");
+        model.save_shapes(&path).unwrap();
+        let loaded = Model::load_shapes(&path).unwrap();
+        remove_file(&path).unwrap();
+        assert_eq!(loaded.polygons[2].shape(), Shapes::Stamp);
+        assert_eq!(loaded.polygons[2].points, model.polygons[2].points);
+        assert_eq!(loaded.polygons[2].stamp(), Some(&stamp));
+    }
+}
@@ -0,0 +1,183 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use error::{Error, Result};
+use image::{DynamicImage, Rgba};
+use image_utils::{dominant_colors, parse_hex_color};
+
+/// first line of a GIMP palette file
+const GPL_HEADER: &str = "GIMP Palette";
+
+/**
+ * A fixed set of colors every shape is painted with.
+ *
+ * Palette files hold one color per line, either as `#rrggbb` or as
+ * `<r> <g> <b>`, empty lines and lines starting with `;` are skipped. GIMP
+ * palettes (`.gpl`) are read as well, their `Name:` and `Columns:` records,
+ * `#` comments and the names after the channels are ignored.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    colors: Vec<Rgba<u8>>,
+}
+
+impl Palette {
+    pub fn load(path: &Path) -> Result<Palette> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut lines = Vec::new();
+        for line in reader.lines() {
+            lines.push(line?);
+        }
+        Palette::parse(&lines).map_err(|e| palette_error(path, &e))
+    }
+
+    /// the `k` dominant colors of `target`
+    pub fn from_target(target: &DynamicImage, k: usize) -> Palette {
+        Palette {
+            colors: dominant_colors(target, k)
+                .into_iter()
+                .map(|(color, _)| color)
+                .collect(),
+        }
+    }
+
+    fn parse<S: AsRef<str>>(lines: &[S]) -> ::std::result::Result<Palette, String> {
+        let gpl = lines
+            .first()
+            .is_some_and(|line| line.as_ref().trim() == GPL_HEADER);
+        let mut colors = Vec::new();
+        for (n, line) in lines.iter().enumerate().skip(if gpl { 1 } else { 0 }) {
+            let line = line.as_ref().trim();
+            let skip = line.is_empty()
+                || line.starts_with(';')
+                || (gpl
+                    && (line.starts_with('#')
+                        || line.starts_with("Name:")
+                        || line.starts_with("Columns:")));
+            if skip {
+                continue;
+            }
+            let color = parse_color(line, gpl)
+                .ok_or_else(|| format!("line {}: expected a color, found `{}`", n + 1, line))?;
+            colors.push(color);
+        }
+        if colors.is_empty() {
+            return Err("no colors found".to_owned());
+        }
+        Ok(Palette { colors: colors })
+    }
+
+    pub fn colors(&self) -> &[Rgba<u8>] {
+        &self.colors
+    }
+
+    /// the palette color closest to `color` in rgb, the alpha is kept; an
+    /// empty palette keeps the color
+    pub fn nearest(&self, color: &Rgba<u8>) -> Rgba<u8> {
+        if self.colors.is_empty() {
+            return *color;
+        }
+        let distance = |c: &Rgba<u8>| {
+            (0..3)
+                .map(|i| {
+                    let d = c.data[i] as i32 - color.data[i] as i32;
+                    d * d
+                })
+                .sum::<i32>()
+        };
+        let mut nearest = self.colors[0];
+        for c in &self.colors[1..] {
+            if distance(c) < distance(&nearest) {
+                nearest = *c;
+            }
+        }
+        nearest.data[3] = color.data[3];
+        nearest
+    }
+}
+
+/// `#rrggbb` or three channels, GIMP palettes may name the color after them
+fn parse_color(line: &str, gpl: bool) -> Option<Rgba<u8>> {
+    if line.starts_with('#') {
+        return parse_hex_color(line);
+    }
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() < 3 || (!gpl && fields.len() > 3) {
+        return None;
+    }
+    let channel = |i: usize| fields[i].parse::<u8>().ok();
+    Some(Rgba {
+        data: [channel(0)?, channel(1)?, channel(2)?, 255],
+    })
+}
+
+fn palette_error(path: &Path, msg: &str) -> Error {
+    Error::InvalidPalette(format!("{}: {}", path.display(), msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    #[test]
+    fn should_parse_color_lists() {
+        let palette = Palette::parse(&["; brand colors", "#ff0000", "", "0 0 255"]).unwrap();
+        assert_eq!(
+            palette.colors(),
+            &[
+                Rgba {
+                    data: [255, 0, 0, 255],
+                },
+                Rgba {
+                    data: [0, 0, 255, 255],
+                },
+            ]
+        );
+        assert!(Palette::parse(&["0 0 256"]).is_err());
+        assert!(Palette::parse(&["0 0 255 blue"]).is_err());
+        assert!(Palette::parse(&["; nothing"]).is_err());
+    }
+
+    #[test]
+    fn should_parse_gimp_palettes() {
+        let lines = [
+            "GIMP Palette",
+            "Name: Brand",
+            "Columns: 2",
+            "#",
+            "255 255 255\tWhite",
+            " 16  32  64\tNavy blue",
+        ];
+        let palette = Palette::parse(&lines).unwrap();
+        assert_eq!(palette.colors().len(), 2);
+        assert_eq!(palette.colors()[1].data, [16, 32, 64, 255]);
+    }
+
+    #[test]
+    fn should_snap_to_nearest_color() {
+        let palette = Palette::parse(&["#000000", "#ffffff", "#ff0000"]).unwrap();
+        let snapped = palette.nearest(&Rgba {
+            data: [200, 40, 30, 128],
+        });
+        assert_eq!(snapped.data, [255, 0, 0, 128]);
+        let snapped = palette.nearest(&Rgba {
+            data: [180, 180, 180, 255],
+        });
+        assert_eq!(snapped.data, [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn should_extract_colors_from_target() {
+        let img = RgbaImage::from_fn(16, 16, |x, _| Rgba {
+            data: if x < 8 {
+                [0, 0, 0, 255]
+            } else {
+                [0, 200, 0, 255]
+            },
+        });
+        let palette = Palette::from_target(&DynamicImage::ImageRgba8(img), 4);
+        assert_eq!(palette.colors().len(), 2);
+    }
+}
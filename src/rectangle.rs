@@ -1,5 +1,5 @@
 use rand::distributions::{IndependentSample, Range};
-use rand::thread_rng;
+use rand::Rng;
 use shape::{Point, Shape};
 
 /**
@@ -15,15 +15,15 @@ pub struct Rectangle {
 }
 
 impl Shape for Rectangle {
-    fn new(range_x: &f32, range_y: &f32) -> Vec<Point> {
-        let mut rng = thread_rng();
-        let base_width = range_x / 8.0;
-        let base_height = range_y / 8.0;
+    fn new<R: Rng>(rng: &mut R, range_x: &f32, range_y: &f32, max_size: Option<f32>) -> Vec<Point> {
+        let max_size = max_size.unwrap_or(f32::INFINITY);
+        let base_width = (range_x / 8.0).min(max_size);
+        let base_height = (range_y / 8.0).min(max_size);
         let x_coord_generator = Range::new(0.0, *range_x - base_width);
         let y_coord_generator = Range::new(0.0, *range_y - base_height);
         let p0 = Point {
-            x: x_coord_generator.ind_sample(&mut rng),
-            y: y_coord_generator.ind_sample(&mut rng),
+            x: x_coord_generator.ind_sample(rng),
+            y: y_coord_generator.ind_sample(rng),
         };
         let p1 = Point {
             x: p0.x + base_width,
@@ -42,10 +42,11 @@ impl Shape for Rectangle {
         points
     }
 
+    /// the middle of the diagonal, rotated rectangles included
     fn center(points: &Vec<Point>) -> Point {
         Point {
-            x: (points[1].x + points[0].x) / 2.0,
-            y: (points[3].y + points[0].y) / 2.0,
+            x: (points[0].x + points[2].x) / 2.0,
+            y: (points[0].y + points[2].y) / 2.0,
         }
     }
 
@@ -64,16 +65,24 @@ impl Shape for Rectangle {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::thread_rng;
 
     #[test]
     fn should_be_within_range() {
-        let points = Rectangle::new(&512.0, &512.0);
+        let points = Rectangle::new(&mut thread_rng(), &512.0, &512.0, None);
         for point in &points {
             assert!(point.x < 512.0);
             assert!(point.y < 512.0);
         }
     }
 
+    #[test]
+    fn should_respect_max_size() {
+        let points = Rectangle::new(&mut thread_rng(), &512.0, &512.0, Some(10.0));
+        assert!((points[1].x - points[0].x - 10.0).abs() < 1e-3);
+        assert!((points[3].y - points[0].y - 10.0).abs() < 1e-3);
+    }
+
     #[test]
     fn should_scale() {
         let p0 = Point { x: 0.0, y: 0.0 };
@@ -1,13 +1,236 @@
 use image::Rgba;
 use image_utils::rgba_to_str;
+use rand::Rng;
 use rectangle::Rectangle;
+use stamp::{self, Stamp};
 use std::f32::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
 use triangle::Triangle;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Shapes {
     Rectangle,
     Triangle,
+    /// the path of a stamp file, see `Polygon::generate_stamp`
+    Stamp,
+}
+
+impl FromStr for Shapes {
+    type Err = String;
+
+    fn from_str(s: &str) -> ::std::result::Result<Shapes, String> {
+        match s {
+            "rectangle" => Ok(Shapes::Rectangle),
+            "triangle" => Ok(Shapes::Triangle),
+            "stamp" => Ok(Shapes::Stamp),
+            _ => Err(format!("unknown shape: {}", s)),
+        }
+    }
+}
+
+impl Shapes {
+    /// the aspect ratio of the least elongated shape, a square or an
+    /// equilateral triangle
+    pub fn min_aspect_ratio(&self) -> f32 {
+        match *self {
+            Shapes::Rectangle | Shapes::Stamp => 1.0,
+            Shapes::Triangle => 2.0 / 3f32.sqrt(),
+        }
+    }
+}
+
+impl fmt::Display for Shapes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Shapes::Rectangle => write!(f, "rectangle"),
+            Shapes::Triangle => write!(f, "triangle"),
+            Shapes::Stamp => write!(f, "stamp"),
+        }
+    }
+}
+
+/// a shape size given in pixels (`12`) or relative to the longest side of
+/// the canvas (`10%`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShapeSize {
+    Pixels(f32),
+    Fraction(f32),
+}
+
+impl ShapeSize {
+    pub fn to_pixels(self, canvas: f32) -> f32 {
+        match self {
+            ShapeSize::Pixels(pixels) => pixels,
+            ShapeSize::Fraction(fraction) => fraction * canvas,
+        }
+    }
+}
+
+impl FromStr for ShapeSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> ::std::result::Result<ShapeSize, String> {
+        let invalid = || format!("invalid size: {} (expected pixels or a percentage)", s);
+        let size = if let Some(percent) = s.strip_suffix('%') {
+            let percent: f32 = percent.parse().map_err(|_| invalid())?;
+            ShapeSize::Fraction(percent / 100.0)
+        } else {
+            ShapeSize::Pixels(s.parse().map_err(|_| invalid())?)
+        };
+        match size {
+            ShapeSize::Pixels(v) | ShapeSize::Fraction(v) if v > 0.0 && v.is_finite() => Ok(size),
+            _ => Err(format!("invalid size: {} (must be greater than zero)", s)),
+        }
+    }
+}
+
+impl fmt::Display for ShapeSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ShapeSize::Pixels(pixels) => write!(f, "{}", pixels),
+            ShapeSize::Fraction(fraction) => write!(f, "{}%", fraction * 100.0),
+        }
+    }
+}
+
+/// size and form limits of new shapes, in pixels of the working image
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ShapeLimits {
+    /// shortest allowed longest edge
+    pub min_size: Option<f32>,
+    /// longest allowed longest edge
+    pub max_size: Option<f32>,
+    /// longest edge divided by the extent across it, at least
+    /// `Shapes::min_aspect_ratio`
+    pub max_aspect_ratio: Option<f32>,
+}
+
+/// kinds of gradient fills
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GradientKind {
+    Linear,
+    Radial,
+}
+
+impl FromStr for GradientKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> ::std::result::Result<GradientKind, String> {
+        match s {
+            "linear" => Ok(GradientKind::Linear),
+            "radial" => Ok(GradientKind::Radial),
+            _ => Err(format!("unknown gradient: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for GradientKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GradientKind::Linear => write!(f, "linear"),
+            GradientKind::Radial => write!(f, "radial"),
+        }
+    }
+}
+
+/**
+ * A gradient fill from the fill color of the polygon to a second color. The
+ * gradient spans the bounding box of the polygon.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gradient {
+    /// along `angle` degrees, clockwise from the x axis
+    Linear { angle: f32, to: Rgba<u8> },
+    /// from the center to the edge of the bounding box
+    Radial { to: Rgba<u8> },
+}
+
+impl Gradient {
+    pub fn kind(&self) -> GradientKind {
+        match *self {
+            Gradient::Linear { .. } => GradientKind::Linear,
+            Gradient::Radial { .. } => GradientKind::Radial,
+        }
+    }
+
+    /// the color at the end of the gradient
+    pub fn to(&self) -> Rgba<u8> {
+        match *self {
+            Gradient::Linear { to, .. } | Gradient::Radial { to } => to,
+        }
+    }
+
+    /// the svg definition of the gradient `id` starting at `from`
+    fn svg(&self, id: usize, from: &Rgba<u8>) -> String {
+        let stops = format!("{}{}", svg_stop(0.0, from), svg_stop(1.0, &self.to()));
+        match *self {
+            Gradient::Linear { angle, .. } => {
+                let (dx, dy) = (deg2rad(&angle).cos() / 2.0, deg2rad(&angle).sin() / 2.0);
+                format!(
+                    "<defs><linearGradient id=\"g{}\" x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\">{}</linearGradient></defs>",
+                    id,
+                    0.5 - dx,
+                    0.5 - dy,
+                    0.5 + dx,
+                    0.5 + dy,
+                    stops
+                )
+            }
+            Gradient::Radial { .. } => format!(
+                "<defs><radialGradient id=\"g{}\" cx=\"0.5\" cy=\"0.5\" r=\"0.5\">{}</radialGradient></defs>",
+                id, stops
+            ),
+        }
+    }
+}
+
+fn svg_stop(offset: f32, color: &Rgba<u8>) -> String {
+    format!(
+        "<stop offset=\"{}\" stop-color=\"rgb({})\" stop-opacity=\"{}\"/>",
+        offset,
+        rgba_to_str(color),
+        color.data[3] as f32 / 255.0
+    )
+}
+
+/// which parts of a shape are painted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+    Fill,
+    Stroke,
+    Both,
+}
+
+impl FromStr for Style {
+    type Err = String;
+
+    fn from_str(s: &str) -> ::std::result::Result<Style, String> {
+        match s {
+            "fill" => Ok(Style::Fill),
+            "stroke" => Ok(Style::Stroke),
+            "both" => Ok(Style::Both),
+            _ => Err(format!("unknown style: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Style {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Style::Fill => write!(f, "fill"),
+            Style::Stroke => write!(f, "stroke"),
+            Style::Both => write!(f, "both"),
+        }
+    }
+}
+
+/// the outline of a polygon, centered on its edges with round joins
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stroke {
+    pub width: f32,
+    pub color: Rgba<u8>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -23,6 +246,13 @@ pub struct Polygon {
     range_y: f32,
     shape: Shapes,
     fill_color: Rgba<u8>,
+    /// blends `fill_color` into a second color when set
+    gradient: Option<Gradient>,
+    /// outline drawn over the fill, a transparent fill without a gradient
+    /// leaves the outline only
+    stroke: Option<Stroke>,
+    /// the path drawn into the rectangle of a `Shapes::Stamp`
+    stamp: Option<Arc<Stamp>>,
 }
 
 fn deg2rad(deg: &f32) -> f32 {
@@ -30,68 +260,270 @@ fn deg2rad(deg: &f32) -> f32 {
 }
 
 impl Polygon {
-    pub fn new(shape: Shapes, range_x: f32, range_y: f32) -> Polygon {
+    pub fn new<R: Rng>(rng: &mut R, shape: Shapes, range_x: f32, range_y: f32) -> Polygon {
+        Polygon::generate(rng, shape, range_x, range_y, None)
+    }
+
+    /**
+     * @brief      random polygon whose longest edge is at most `max_size`
+     *             pixels, if given; a `Shapes::Stamp` is a rectangle without
+     *             a path, see `Polygon::generate_stamp`
+     */
+    pub fn generate<R: Rng>(
+        rng: &mut R,
+        shape: Shapes,
+        range_x: f32,
+        range_y: f32,
+        max_size: Option<f32>,
+    ) -> Polygon {
         let default_white_color = Rgba {
             data: [255 as u8, 255 as u8, 255 as u8, 255],
         };
 
         match shape {
-            Shapes::Rectangle => Polygon {
-                points: Rectangle::new(&range_x, &range_y),
+            Shapes::Rectangle | Shapes::Stamp => Polygon {
+                points: Rectangle::new(rng, &range_x, &range_y, max_size),
                 range_x: range_x,
                 range_y: range_y,
                 shape: shape,
                 fill_color: default_white_color,
+                gradient: None,
+                stroke: None,
+                stamp: None,
             },
             Shapes::Triangle => Polygon {
-                points: Triangle::new(&range_x, &range_y),
+                points: Triangle::new(rng, &range_x, &range_y, max_size),
                 range_x: range_x,
                 range_y: range_y,
                 shape: shape,
                 fill_color: default_white_color,
+                gradient: None,
+                stroke: None,
+                stamp: None,
             },
         }
     }
 
+    /**
+     * @brief      random polygon of `stamp`: a rectangle in the proportions
+     *             of the area the path covers, its longest edge at most
+     *             `max_size` pixels, if given
+     */
+    pub fn generate_stamp<R: Rng>(
+        rng: &mut R,
+        stamp: Arc<Stamp>,
+        range_x: f32,
+        range_y: f32,
+        max_size: Option<f32>,
+    ) -> Polygon {
+        let mut polygon = Polygon::generate(rng, Shapes::Stamp, range_x, range_y, max_size);
+        let width = polygon.points[1].x - polygon.points[0].x;
+        let height = polygon.points[3].y - polygon.points[0].y;
+        let ratio = stamp.aspect_ratio();
+        if height > width * ratio {
+            Rectangle::scale(&mut polygon.points, &1.0, &(width * ratio / height));
+        } else {
+            Rectangle::scale(&mut polygon.points, &(height / ratio / width), &1.0);
+        }
+        polygon.stamp = Some(stamp);
+        polygon
+    }
+
+    pub fn from_points(
+        shape: Shapes,
+        points: Vec<Point>,
+        range_x: f32,
+        range_y: f32,
+        fill_color: Rgba<u8>,
+    ) -> Polygon {
+        Polygon {
+            points: points,
+            range_x: range_x,
+            range_y: range_y,
+            shape: shape,
+            fill_color: fill_color,
+            gradient: None,
+            stroke: None,
+            stamp: None,
+        }
+    }
+
     pub fn set_fill_color(&mut self, color: Rgba<u8>) {
         self.fill_color = color;
     }
 
-    pub fn svg(&self) -> String {
-        let mut points_str = String::new();
-        for point in &self.points {
-            // println!("{},{}", point.x, point.y);
-            points_str.push_str(&format!("{},{} ", point.x, point.y));
-        }
-        format!(
-            "<polygon points=\"{}\" fill=\"rgb({})\" fill-opacity=\"0.7\"/>",
-            points_str,
-            rgba_to_str(&self.fill_color)
-        )
+    pub fn fill_color(&self) -> Rgba<u8> {
+        self.fill_color
     }
 
-    pub fn get_bounds(&self) -> [Point; 2] {
-        let mut max_x = 0.0;
-        let mut max_y = 0.0;
-        let mut min_x = 0.0;
-        let mut min_y = 0.0;
-        for point in &self.points {
-            if point.x > max_x {
-                max_x = point.x;
-            } else if point.x < min_x {
-                min_x = point.x;
+    pub fn set_gradient(&mut self, gradient: Option<Gradient>) {
+        self.gradient = gradient;
+    }
+
+    pub fn gradient(&self) -> Option<Gradient> {
+        self.gradient
+    }
+
+    pub fn set_stroke(&mut self, stroke: Option<Stroke>) {
+        self.stroke = stroke;
+    }
+
+    pub fn stroke(&self) -> Option<Stroke> {
+        self.stroke
+    }
+
+    pub fn set_stamp(&mut self, stamp: Option<Arc<Stamp>>) {
+        self.stamp = stamp;
+    }
+
+    pub fn stamp(&self) -> Option<&Arc<Stamp>> {
+        self.stamp.as_ref()
+    }
+
+    pub fn shape(&self) -> Shapes {
+        self.shape
+    }
+
+    /// the svg elements of the polygon, `id` tells the gradients of a
+    /// document apart; a stamp draws its path inline
+    pub fn svg(&self, id: usize) -> String {
+        self.svg_element(id, true)
+    }
+
+    /// like `svg`, but a stamp refers to the path defined by
+    /// `Stamp::svg_definition` with `<use>`
+    pub fn svg_use(&self, id: usize) -> String {
+        self.svg_element(id, false)
+    }
+
+    fn svg_element(&self, id: usize, inline: bool) -> String {
+        let points = self.clipped();
+        if points.is_empty() {
+            return String::new();
+        }
+        // the transform of a stamp scales its stroke as well
+        let (element, scale) = match self.stamp {
+            Some(ref stamp) if inline => (
+                format!(
+                    "path d=\"{}\" transform=\"{}\"",
+                    stamp.path(),
+                    stamp.transform(&self.points)
+                ),
+                stamp.scale(&self.points),
+            ),
+            Some(ref stamp) => (
+                format!(
+                    "use xlink:href=\"#{}\" transform=\"{}\"",
+                    stamp::SVG_ID,
+                    stamp.transform(&self.points)
+                ),
+                stamp.scale(&self.points),
+            ),
+            None => {
+                // a stroke follows the real edges and the viewBox clips it,
+                // the clipped polygon would add strokes along the border
+                let outline = if self.stroke.is_some() {
+                    &self.points
+                } else {
+                    &points
+                };
+                let mut points_str = String::new();
+                for point in outline {
+                    points_str.push_str(&format!("{},{} ", point.x, point.y));
+                }
+                (format!("polygon points=\"{}\"", points_str), 1.0)
             }
-            if point.y > max_y {
-                max_y = point.y;
-            } else if point.y < min_y {
-                min_y = point.y;
+        };
+        let stroke = match self.stroke {
+            Some(ref stroke) => format!(
+                " stroke=\"rgb({})\" stroke-opacity=\"{}\" stroke-width=\"{}\" stroke-linejoin=\"round\"",
+                rgba_to_str(&stroke.color),
+                stroke.color.data[3] as f32 / 255.0,
+                stroke.width / scale.max(f32::EPSILON)
+            ),
+            None => String::new(),
+        };
+        let (defs, fill) = match self.gradient {
+            Some(ref gradient) => (
+                gradient.svg(id, &self.fill_color),
+                format!(" fill=\"url(#g{})\"", id),
+            ),
+            None if self.fill_color.data[3] == 0 && self.stroke.is_some() => {
+                (String::new(), " fill=\"none\"".to_owned())
             }
+            None => (
+                String::new(),
+                format!(
+                    " fill=\"rgb({})\" fill-opacity=\"{}\"",
+                    rgba_to_str(&self.fill_color),
+                    self.fill_color.data[3] as f32 / 255.0
+                ),
+            ),
+        };
+        format!("{}<{}{}{}/>", defs, element, fill, stroke)
+    }
+
+    /**
+     * @brief      bounding box of the part of the polygon that lies on the
+     *             canvas
+     *
+     * @return     [min, max], both at the closest canvas point when the
+     *             polygon lies outside of the canvas
+     */
+    pub fn get_bounds(&self) -> [Point; 2] {
+        let points = self.clipped();
+        if points.is_empty() {
+            let center = self.center();
+            let outside = Point {
+                x: center.x.max(0.0).min(self.range_x),
+                y: center.y.max(0.0).min(self.range_y),
+            };
+            return [outside.clone(), outside];
+        }
+        let mut min = points[0].clone();
+        let mut max = points[0].clone();
+        for point in &points[1..] {
+            min.x = min.x.min(point.x);
+            min.y = min.y.min(point.y);
+            max.x = max.x.max(point.x);
+            max.y = max.y.max(point.y);
         }
-        let min = Point { x: min_x, y: min_y };
-        let max = Point { x: max_x, y: max_y };
         [min, max]
     }
 
+    /// bounding box of every pixel the polygon paints, the fill and half
+    /// of the stroke outside of it, clamped to the canvas
+    pub fn covered_bounds(&self) -> [Point; 2] {
+        let [min, max] = self.get_bounds();
+        let margin = self.stroke.map_or(0.0, |stroke| stroke.width / 2.0);
+        [
+            Point {
+                x: (min.x - margin).max(0.0),
+                y: (min.y - margin).max(0.0),
+            },
+            Point {
+                x: (max.x + margin).min(self.range_x),
+                y: (max.y + margin).min(self.range_y),
+            },
+        ]
+    }
+
+    /**
+     * @brief      the polygon clipped to the canvas with the
+     *             Sutherland-Hodgman algorithm, the vertices on the canvas
+     *             keep their position so the shape keeps its form
+     *
+     * @return     the vertices of the clipped polygon, empty when it lies
+     *             outside of the canvas
+     */
+    pub fn clipped(&self) -> Vec<Point> {
+        let (range_x, range_y) = (self.range_x, self.range_y);
+        let points = clip_edge(&self.points, |p| p.x >= 0.0, |a, b| at_x(a, b, 0.0));
+        let points = clip_edge(&points, |p| p.x <= range_x, |a, b| at_x(a, b, range_x));
+        let points = clip_edge(&points, |p| p.y >= 0.0, |a, b| at_y(a, b, 0.0));
+        clip_edge(&points, |p| p.y <= range_y, |a, b| at_y(a, b, range_y))
+    }
+
     pub fn rotate(&mut self, deg: &f32) {
         let radians = deg2rad(deg);
         let sin = radians.sin();
@@ -109,7 +541,6 @@ impl Polygon {
             point.y = y + r_y;
         }
         // debug!("values after rotation: {:?}", self.points);
-        self.clamp_values();
     }
 
     pub fn scale(&mut self, scale_x: &f32, scale_y: &f32) {
@@ -120,40 +551,209 @@ impl Polygon {
         // self.clamp_values();
         match self.shape {
             Shapes::Rectangle => Rectangle::scale(&mut self.points, scale_x, scale_y),
-            _ => (),
+            // a stamp keeps the proportions of its path
+            Shapes::Stamp => Rectangle::scale(&mut self.points, scale_x, scale_x),
+            Shapes::Triangle => Triangle::scale(&mut self.points, scale_x, scale_y),
         };
-        self.clamp_values();
     }
 
-    fn center(&self) -> Point {
+    /// translates the polygon so that its center lands on `center`
+    pub fn move_to(&mut self, center: &Point) {
+        let current = self.center();
+        let (dx, dy) = (center.x - current.x, center.y - current.y);
+        for point in &mut self.points {
+            point.x += dx;
+            point.y += dy;
+        }
+    }
+
+    /// the centroid of the shape, shapes are rotated around it and placed
+    /// by it
+    pub fn center(&self) -> Point {
         match self.shape {
-            Shapes::Rectangle => Rectangle::center(&self.points),
-            _ => self.points[0].clone(),
+            Shapes::Rectangle | Shapes::Stamp => Rectangle::center(&self.points),
+            Shapes::Triangle => Triangle::center(&self.points),
         }
     }
 
-    fn clamp_values(&mut self) {
-        for point in &mut self.points {
-            if point.x >= self.range_x {
-                // debug!("clamping value for shape: from: {}, to: {}", point.x, self.range_x);
-                point.x = self.range_x - 1.0;
-            } else if point.x < 0.0 {
-                // debug!("clamping value for shape: from: {}, to: {}", point.x, 0.0);
-                point.x = 0.0;
+    /// length of the longest edge
+    pub fn size(&self) -> f32 {
+        self.edge(self.longest_edge())
+    }
+
+    /**
+     * @brief      how elongated the shape is: the longer side divided by the
+     *             shorter one for rectangles, the longest edge divided by the
+     *             height over it for triangles
+     */
+    pub fn aspect_ratio(&self) -> f32 {
+        let size = self.size();
+        let across = match self.shape {
+            Shapes::Rectangle | Shapes::Stamp => self.edge(0).min(self.edge(1)),
+            Shapes::Triangle => 2.0 * self.area() / size,
+        };
+        if across > 0.0 {
+            size / across
+        } else {
+            f32::INFINITY
+        }
+    }
+
+    /**
+     * @brief      reshapes the polygon to fit `limits`, the center and the
+     *             orientation are kept
+     */
+    pub fn constrain(&mut self, limits: &ShapeLimits) {
+        if let Some(max_aspect_ratio) = limits.max_aspect_ratio {
+            if self.aspect_ratio() > max_aspect_ratio {
+                self.widen(self.size() / max_aspect_ratio);
+            }
+        }
+        let size = self.size();
+        let mut target = size;
+        if let Some(max_size) = limits.max_size {
+            target = target.min(max_size);
+        }
+        if let Some(min_size) = limits.min_size {
+            target = target.max(min_size);
+        }
+        if size > 0.0 && target != size {
+            let factor = target / size;
+            let centroid = self.centroid();
+            for point in &mut self.points {
+                point.x = centroid.x + (point.x - centroid.x) * factor;
+                point.y = centroid.y + (point.y - centroid.y) * factor;
+            }
+        }
+    }
+
+    /// stretches the shape across its longest edge to the extent `across`
+    fn widen(&mut self, across: f32) {
+        let i = self.longest_edge();
+        let n = self.points.len();
+        let (a, b) = (self.points[i].clone(), self.points[(i + 1) % n].clone());
+        let length = self.edge(i);
+        if length <= 0.0 {
+            return;
+        }
+        // unit normal of the longest edge
+        let (nx, ny) = (-(b.y - a.y) / length, (b.x - a.x) / length);
+        match self.shape {
+            Shapes::Rectangle => {
+                // move the longest edge and its opposite edge apart
+                let center = self.centroid();
+                let d_a = (a.x - center.x) * nx + (a.y - center.y) * ny;
+                let side_a = if d_a > 0.0 { 1.0 } else { -1.0 };
+                for (j, point) in self.points.iter_mut().enumerate() {
+                    let side = if j == i || j == (i + 1) % n {
+                        side_a
+                    } else {
+                        -side_a
+                    };
+                    let d = (point.x - center.x) * nx + (point.y - center.y) * ny;
+                    let shift = side * across / 2.0 - d;
+                    point.x += nx * shift;
+                    point.y += ny * shift;
+                }
             }
-            if point.y >= self.range_y {
-                // debug!("clamping value for shape: from: {}, to: {}", point.y, self.range_y);
-                point.y = self.range_y - 1.0;
-            } else if point.y < 0.0 {
-                // debug!("clamping value for shape: from: {}, to: {}", point.y, 0.0);
-                point.y = 0.0;
+            // the proportions of a stamp are those of its path, which is why
+            // Options::validate rejects a maximum aspect ratio with a stamp
+            Shapes::Stamp => (),
+            Shapes::Triangle => {
+                // move the apex away from the longest edge, keeping its foot
+                // where the other edges stay shorter; the equilateral
+                // triangle is the widest there is
+                let across = across.min(length * 3f32.sqrt() / 2.0);
+                let reach = (length * length - across * across).max(0.0).sqrt();
+                let (ux, uy) = ((b.x - a.x) / length, (b.y - a.y) / length);
+                let apex = &mut self.points[(i + 2) % n];
+                let d = (apex.x - a.x) * nx + (apex.y - a.y) * ny;
+                let side = if d < 0.0 { -1.0 } else { 1.0 };
+                let t = ((apex.x - a.x) * ux + (apex.y - a.y) * uy)
+                    .max(length - reach)
+                    .min(reach);
+                apex.x = a.x + ux * t + nx * side * across;
+                apex.y = a.y + uy * t + ny * side * across;
             }
         }
     }
+
+    fn edge(&self, i: usize) -> f32 {
+        let a = &self.points[i];
+        let b = &self.points[(i + 1) % self.points.len()];
+        ((b.x - a.x).powi(2) + (b.y - a.y).powi(2)).sqrt()
+    }
+
+    fn longest_edge(&self) -> usize {
+        (0..self.points.len()).fold(0, |longest, i| {
+            if self.edge(i) > self.edge(longest) {
+                i
+            } else {
+                longest
+            }
+        })
+    }
+
+    fn area(&self) -> f32 {
+        let mut area = 0.0;
+        for (i, a) in self.points.iter().enumerate() {
+            let b = &self.points[(i + 1) % self.points.len()];
+            area += a.x * b.y - b.x * a.y;
+        }
+        (area / 2.0).abs()
+    }
+
+    fn centroid(&self) -> Point {
+        let n = self.points.len() as f32;
+        Point {
+            x: self.points.iter().map(|p| p.x).sum::<f32>() / n,
+            y: self.points.iter().map(|p| p.y).sum::<f32>() / n,
+        }
+    }
+}
+
+/// keeps the part of the polygon on the inner side of one canvas edge
+fn clip_edge<I, X>(points: &[Point], inside: I, intersect: X) -> Vec<Point>
+where
+    I: Fn(&Point) -> bool,
+    X: Fn(&Point, &Point) -> Point,
+{
+    let mut clipped = Vec::with_capacity(points.len() + 1);
+    for (i, current) in points.iter().enumerate() {
+        let previous = &points[(i + points.len() - 1) % points.len()];
+        match (inside(previous), inside(current)) {
+            (true, true) => clipped.push(current.clone()),
+            (true, false) => clipped.push(intersect(previous, current)),
+            (false, true) => {
+                clipped.push(intersect(previous, current));
+                clipped.push(current.clone());
+            }
+            (false, false) => (),
+        }
+    }
+    clipped
+}
+
+/// point of the segment a-b with the given x
+fn at_x(a: &Point, b: &Point, x: f32) -> Point {
+    let t = (x - a.x) / (b.x - a.x);
+    Point {
+        x: x,
+        y: a.y + t * (b.y - a.y),
+    }
+}
+
+/// point of the segment a-b with the given y
+fn at_y(a: &Point, b: &Point, y: f32) -> Point {
+    let t = (y - a.y) / (b.y - a.y);
+    Point {
+        x: a.x + t * (b.x - a.x),
+        y: y,
+    }
 }
 
 pub trait Shape {
-    fn new(range_x: &f32, range_y: &f32) -> Vec<Point>;
+    fn new<R: Rng>(rng: &mut R, range_x: &f32, range_y: &f32, max_size: Option<f32>) -> Vec<Point>;
     fn center(points: &Vec<Point>) -> Point;
     fn scale(points: &mut Vec<Point>, scale_x: &f32, scale_y: &f32);
 }
@@ -161,15 +761,287 @@ pub trait Shape {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::thread_rng;
 
     #[test]
     fn should_rotate_rectangle() {
-        // let polygon = Polygon::new("triangle", 512, 512);
-        // polygon.points.push();
+        let mut polygon = rectangle(10.0, 20.0, 40.0, 20.0);
+        polygon.rotate(&90.0);
+        // a quarter turn about the center (30, 30)
+        let expected = [(40.0, 10.0), (40.0, 50.0), (20.0, 50.0), (20.0, 10.0)];
+        for (point, &(x, y)) in polygon.points.iter().zip(&expected) {
+            assert!((point.x - x).abs() < 1e-4 && (point.y - y).abs() < 1e-4);
+        }
+    }
 
-        // for point in &polygon.points {
-        //     assert!(point.x < 512);
-        //     assert!(point.y < 512);
-        // }
+    fn rectangle(x: f32, y: f32, width: f32, height: f32) -> Polygon {
+        Polygon::from_points(
+            Shapes::Rectangle,
+            vec![
+                Point { x: x, y: y },
+                Point { x: x + width, y: y },
+                Point {
+                    x: x + width,
+                    y: y + height,
+                },
+                Point {
+                    x: x,
+                    y: y + height,
+                },
+            ],
+            100.0,
+            100.0,
+            Rgba {
+                data: [0, 0, 0, 255],
+            },
+        )
+    }
+
+    fn area(points: &[Point]) -> f32 {
+        let mut area = 0.0;
+        for (i, a) in points.iter().enumerate() {
+            let b = &points[(i + 1) % points.len()];
+            area += a.x * b.y - b.x * a.y;
+        }
+        (area / 2.0).abs()
+    }
+
+    fn distance(a: &Point, b: &Point) -> f32 {
+        ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt()
+    }
+
+    #[test]
+    fn should_compute_bounds_away_from_origin() {
+        let polygon = rectangle(20.0, 30.0, 10.0, 5.0);
+        let bounds = polygon.get_bounds();
+        assert_eq!(bounds[0], Point { x: 20.0, y: 30.0 });
+        assert_eq!(bounds[1], Point { x: 30.0, y: 35.0 });
+    }
+
+    #[test]
+    fn should_clip_to_canvas() {
+        // half of the rectangle hangs over the right edge
+        let polygon = rectangle(90.0, 10.0, 20.0, 10.0);
+        let clipped = polygon.clipped();
+        assert_eq!(area(&clipped), 100.0);
+        let bounds = polygon.get_bounds();
+        assert_eq!(bounds[0], Point { x: 90.0, y: 10.0 });
+        assert_eq!(bounds[1], Point { x: 100.0, y: 20.0 });
+        // the vertices on the canvas are not moved
+        assert!(clipped.contains(&Point { x: 90.0, y: 10.0 }));
+        assert!(clipped.contains(&Point { x: 90.0, y: 20.0 }));
+
+        let outside = rectangle(120.0, 10.0, 20.0, 10.0);
+        assert!(outside.clipped().is_empty());
+        assert_eq!(outside.svg(0), "");
+    }
+
+    quickcheck! {
+        fn clipped_polygon_should_stay_on_canvas(x: u8, y: u8, angle: u16, sx: u8, sy: u8) -> bool {
+            let mut polygon = rectangle(x as f32 / 2.55, y as f32 / 2.55, 20.0, 10.0);
+            polygon.scale(&(0.5 + sx as f32 / 128.0), &(0.5 + sy as f32 / 128.0));
+            polygon.rotate(&((angle % 360) as f32));
+            let clipped = polygon.clipped();
+            let bounds = polygon.get_bounds();
+            bounds[0].x <= bounds[1].x
+                && bounds[0].y <= bounds[1].y
+                && clipped.iter().all(|p| {
+                    p.x >= 0.0 && p.x <= 100.0 && p.y >= 0.0 && p.y <= 100.0
+                        && p.x >= bounds[0].x && p.x <= bounds[1].x
+                        && p.y >= bounds[0].y && p.y <= bounds[1].y
+                })
+                && area(&clipped) <= area(&polygon.points) + 1e-2
+        }
+
+        fn transforms_should_keep_the_form(x: u8, y: u8, angle: u16, sx: u8, sy: u8) -> bool {
+            let mut polygon = rectangle(x as f32 / 2.55, y as f32 / 2.55, 20.0, 10.0);
+            let (sx, sy) = (0.5 + sx as f32 / 128.0, 0.5 + sy as f32 / 128.0);
+            polygon.scale(&sx, &sy);
+            polygon.rotate(&((angle % 360) as f32));
+            let p = &polygon.points;
+            // still a rectangle of the scaled size, wherever it lies
+            (distance(&p[0], &p[1]) - 20.0 * sx).abs() < 1e-3
+                && (distance(&p[1], &p[2]) - 10.0 * sy).abs() < 1e-3
+                && (distance(&p[0], &p[2]) - distance(&p[1], &p[3])).abs() < 1e-3
+        }
+
+        fn inner_polygons_should_not_change(x: u8, y: u8, angle: u16) -> bool {
+            let mut polygon = rectangle(30.0 + x as f32 / 10.0, 30.0 + y as f32 / 10.0, 10.0, 10.0);
+            polygon.rotate(&((angle % 360) as f32));
+            polygon.clipped() == polygon.points
+        }
+    }
+
+    #[test]
+    fn should_emit_gradient_definitions() {
+        let mut polygon = rectangle(10.0, 10.0, 20.0, 20.0);
+        assert!(polygon.svg(3).contains("fill=\"rgb(0,0,0)\""));
+        polygon.set_gradient(Some(Gradient::Linear {
+            angle: 90.0,
+            to: Rgba {
+                data: [255, 0, 0, 51],
+            },
+        }));
+        let svg = polygon.svg(3);
+        assert!(svg.starts_with("<defs><linearGradient id=\"g3\" x1=\"0.5\" y1=\"0\""));
+        assert!(
+            svg.contains("<stop offset=\"1\" stop-color=\"rgb(255,0,0)\" stop-opacity=\"0.2\"/>")
+        );
+        assert!(svg.ends_with("fill=\"url(#g3)\"/>"));
+
+        polygon.set_gradient(Some(Gradient::Radial {
+            to: Rgba {
+                data: [255, 0, 0, 255],
+            },
+        }));
+        assert!(polygon.svg(4).contains("<radialGradient id=\"g4\""));
+    }
+
+    #[test]
+    fn should_emit_strokes() {
+        let mut polygon = rectangle(10.0, 10.0, 20.0, 20.0);
+        polygon.set_stroke(Some(Stroke {
+            width: 4.0,
+            color: Rgba {
+                data: [0, 0, 255, 255],
+            },
+        }));
+        let svg = polygon.svg(0);
+        assert!(svg.contains("fill=\"rgb(0,0,0)\""));
+        assert!(svg.ends_with(
+            "stroke=\"rgb(0,0,255)\" stroke-opacity=\"1\" stroke-width=\"4\" stroke-linejoin=\"round\"/>"
+        ));
+        polygon.set_fill_color(Rgba { data: [0, 0, 0, 0] });
+        assert!(polygon.svg(0).contains("fill=\"none\" stroke="));
+
+        let bounds = polygon.covered_bounds();
+        assert_eq!(bounds[0], Point { x: 8.0, y: 8.0 });
+        assert_eq!(bounds[1], Point { x: 32.0, y: 32.0 });
+        let mut corner = rectangle(0.0, 0.0, 20.0, 20.0);
+        corner.set_stroke(polygon.stroke());
+        assert_eq!(corner.covered_bounds()[0], Point { x: 0.0, y: 0.0 });
+
+        // no stroke along the canvas border
+        let mut overhanging = rectangle(90.0, 10.0, 20.0, 20.0);
+        assert!(overhanging.svg(0).contains("points=\"90,10 100,10 "));
+        overhanging.set_stroke(polygon.stroke());
+        assert!(overhanging
+            .svg(0)
+            .contains("points=\"90,10 110,10 110,30 90,30 \""));
+    }
+
+    #[test]
+    fn should_keep_stamp_proportions() {
+        let stamp = Arc::new(Stamp::new(
+            "M0 0 H10 V20 Z".to_owned(),
+            [Point { x: 0.0, y: 0.0 }, Point { x: 10.0, y: 20.0 }],
+        ));
+        let proportions = |polygon: &Polygon| polygon.edge(1) / polygon.edge(0);
+        let mut polygon = Polygon::generate_stamp(&mut thread_rng(), stamp, 100.0, 100.0, None);
+        assert_eq!(polygon.shape(), Shapes::Stamp);
+        assert!((proportions(&polygon) - 2.0).abs() < 1e-3);
+        polygon.scale(&0.5, &2.0);
+        assert!((proportions(&polygon) - 2.0).abs() < 1e-3);
+        assert!(polygon
+            .svg(0)
+            .starts_with("<path d=\"M0 0 H10 V20 Z\" transform=\"matrix("));
+    }
+
+    #[test]
+    fn should_parse_shape_sizes() {
+        assert_eq!("12".parse(), Ok(ShapeSize::Pixels(12.0)));
+        assert_eq!("25%".parse(), Ok(ShapeSize::Fraction(0.25)));
+        assert_eq!(ShapeSize::Fraction(0.25).to_pixels(200.0), 50.0);
+        assert!("0".parse::<ShapeSize>().is_err());
+        assert!("big".parse::<ShapeSize>().is_err());
+    }
+
+    #[test]
+    fn should_constrain_size_and_aspect_ratio() {
+        let limits = ShapeLimits {
+            min_size: Some(10.0),
+            max_size: Some(40.0),
+            max_aspect_ratio: Some(4.0),
+        };
+        // a sliver is widened
+        let mut polygon = rectangle(10.0, 10.0, 40.0, 1.0);
+        polygon.constrain(&limits);
+        assert!((polygon.aspect_ratio() - 4.0).abs() < 1e-4);
+        assert!((polygon.size() - 40.0).abs() < 1e-4);
+
+        // a wash is shrunk around its center
+        let mut polygon = rectangle(0.0, 0.0, 80.0, 60.0);
+        polygon.constrain(&limits);
+        assert!((polygon.size() - 40.0).abs() < 1e-4);
+        assert_eq!(polygon.centroid(), Point { x: 40.0, y: 30.0 });
+
+        // a speck is grown
+        let mut polygon = rectangle(50.0, 50.0, 2.0, 1.0);
+        polygon.constrain(&limits);
+        assert!((polygon.size() - 10.0).abs() < 1e-4);
+    }
+
+    quickcheck! {
+        fn constrained_triangles_should_fit(ax: u8, ay: u8, bx: u8, by: u8, cx: u8, cy: u8) -> bool {
+            let points = vec![
+                Point { x: ax as f32, y: ay as f32 },
+                Point { x: bx as f32, y: by as f32 },
+                Point { x: cx as f32, y: cy as f32 },
+            ];
+            let mut polygon = Polygon::from_points(
+                Shapes::Triangle,
+                points,
+                256.0,
+                256.0,
+                Rgba { data: [0, 0, 0, 255] },
+            );
+            if polygon.size() == 0.0 {
+                return true;
+            }
+            let limits = ShapeLimits {
+                min_size: Some(8.0),
+                max_size: Some(64.0),
+                max_aspect_ratio: Some(5.0),
+            };
+            polygon.constrain(&limits);
+            polygon.size() >= 8.0 - 1e-3
+                && polygon.size() <= 64.0 + 1e-3
+                && polygon.aspect_ratio() <= 5.0 + 1e-3
+        }
+
+        fn constrained_rectangles_should_fit(w: u8, h: u8, angle: u16) -> bool {
+            let mut polygon = rectangle(50.0, 50.0, w as f32 + 1.0, h as f32 / 16.0 + 0.1);
+            polygon.rotate(&((angle % 360) as f32));
+            let limits = ShapeLimits {
+                min_size: Some(8.0),
+                max_size: Some(64.0),
+                max_aspect_ratio: Some(5.0),
+            };
+            polygon.constrain(&limits);
+            polygon.size() >= 8.0 - 1e-3
+                && polygon.size() <= 64.0 + 1e-3
+                && polygon.aspect_ratio() <= 5.0 + 1e-3
+        }
+    }
+
+    #[test]
+    fn should_move_rectangle() {
+        let mut polygon = Polygon::from_points(
+            Shapes::Rectangle,
+            vec![
+                Point { x: 0.0, y: 0.0 },
+                Point { x: 10.0, y: 0.0 },
+                Point { x: 10.0, y: 4.0 },
+                Point { x: 0.0, y: 4.0 },
+            ],
+            100.0,
+            100.0,
+            Rgba {
+                data: [0, 0, 0, 255],
+            },
+        );
+        polygon.move_to(&Point { x: 50.0, y: 20.0 });
+        assert_eq!(polygon.points[0], Point { x: 45.0, y: 18.0 });
+        assert_eq!(polygon.points[2], Point { x: 55.0, y: 22.0 });
     }
 }
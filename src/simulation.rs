@@ -1,131 +1,1769 @@
-use image::DynamicImage;
+use color_mode::ColorMode;
 use image::GenericImage;
-use image_utils::{get_average_color, image_area_diff};
+use image::{DynamicImage, FilterType, Rgba, RgbaImage};
+use image_utils::{dominant_colors, get_average_color, opacity, parse_hex_color, pixel_errors};
 use individual::GImage;
 use individual::Individual;
+use metric::Metrics;
+use model::Model;
+use palette::Palette;
+use rand::{SeedableRng, StdRng};
 use scoped_threadpool::Pool;
-use shape::{Polygon, Shapes};
+use shape::{GradientKind, Point, Polygon, ShapeLimits, ShapeSize, Shapes, Style};
+use stamp::Stamp;
 use std::default::Default;
-use std::path::Path;
-use std::sync::{Arc, RwLock};
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use weights::{weight_at, AutoWeights, Weights};
 
-use error::Result;
+use error::{Error, Result};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Options {
-    pub pop_size: u32,
     pub shape: String,
+    /// svg file whose single path is used as the shape instead of `shape`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stamp: Option<PathBuf>,
     pub max_iter: u32,
     pub num_genes: u32,
+    /// opacity of every added shape
+    pub alpha: f32,
+    /// distance to the target that is minimized: rmse, ssim, ms-ssim or lab;
+    /// the structural ones compare the luma of 8x8 blocks and ignore hue
+    pub metric: String,
+    /// grayscale mask, bright pixels are reproduced with more fidelity and
+    /// attract more shapes; resized to the working resolution
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weights: Option<PathBuf>,
+    /// weight map computed from the target instead of a mask: edges or
+    /// saliency
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auto_weights: Option<String>,
+    /// where new shapes are placed: uniform or error, the latter draws
+    /// positions from the current per-pixel error
+    pub placement: String,
+    /// smallest shape, in pixels (`4`) or relative to the longest side of
+    /// the canvas (`2%`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_shape_size: Option<String>,
+    /// largest shape, in pixels or relative to the canvas like
+    /// `min_shape_size`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_shape_size: Option<String>,
+    /// longest edge of a shape divided by its extent across it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_aspect_ratio: Option<f32>,
+    /// color below the shapes: auto, mean, dominant, transparent or an
+    /// explicit `#rrggbb`
+    pub background: String,
+    /// gradient fills new shapes may get besides a flat color: linear,
+    /// radial or both as `linear,radial`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gradients: Option<String>,
+    /// whether shapes are filled, outlined by a stroke or both: fill, stroke
+    /// or both
+    pub style: String,
+    /// how shape colors are chosen: color, grayscale, mono (one ink of
+    /// varying opacity) or duotone (a blend of two inks)
+    pub color_mode: String,
+    /// `#rrggbb` ink of mono, `#rrggbb,#rrggbb` shadow and highlight of
+    /// duotone
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inks: Option<String>,
+    /// file with the colors every shape is painted with, one per line or a
+    /// GIMP palette
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub palette: Option<PathBuf>,
+    /// paint with this many colors extracted from the target instead of a
+    /// palette file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub palette_size: Option<u32>,
+    /// shrinks the largest allowed shape as shapes are added: linear,
+    /// exponential or step, from `max_shape_size` to `min_shape_size`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size_schedule: Option<String>,
+    /// seed for the random generator, runs with the same seed and options
+    /// are repeatable regardless of the number of threads
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// downscale the target so that its longest side fits, the output is
+    /// rendered at the original size again
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u32>,
+    /// raster or svg output used by the command line, the shape file is
+    /// written next to it
+    pub output: PathBuf,
+    /// number of threads used to score mutations
+    pub threads: u32,
+    /// stop once the whole image scores at or below this value, in the units
+    /// of `metric`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_score: Option<f32>,
+    /// stop after this many seconds of wall-clock time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_limit: Option<u64>,
+    /// stop once the result holds this many shapes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_shapes: Option<u32>,
+    /// stop after this many consecutive generations without improvement
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_stagnation: Option<u32>,
+    /// used by the command line to save a raster after every accepted shape
     pub render_debug_rasters: bool,
 }
 
+/// how the positions of new shapes are picked
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Placement {
+    /// anywhere on the canvas, or proportional to the weights if given
+    Uniform,
+    /// proportional to the weighted error of the current image
+    Error,
+}
+
+impl FromStr for Placement {
+    type Err = String;
+
+    fn from_str(s: &str) -> ::std::result::Result<Placement, String> {
+        match s {
+            "uniform" => Ok(Placement::Uniform),
+            "error" => Ok(Placement::Error),
+            _ => Err(format!("unknown placement: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Placement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Placement::Uniform => write!(f, "uniform"),
+            Placement::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// how the color below the shapes is picked
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Background {
+    /// transparent for targets with transparent pixels, the dominant color
+    /// if it covers most of the target, else the mean
+    Auto,
+    Mean,
+    /// the largest k-means cluster of the target colors
+    Dominant,
+    Color(Rgba<u8>),
+    /// no background, for compositing the result over other artwork
+    Transparent,
+}
+
+/// number of clusters the target colors are grouped into for `Dominant`
+const BACKGROUND_CLUSTERS: usize = 5;
+/// share of the target the dominant color needs for `Auto` to pick it
+const AUTO_BACKGROUND_SHARE: f32 = 0.5;
+
+impl Background {
+    /// the background color for `target`
+    pub fn color(&self, target: Arc<DynamicImage>) -> Rgba<u8> {
+        match *self {
+            Background::Auto if opacity(&target).is_some() => Background::Transparent.color(target),
+            Background::Auto => match dominant_colors(&target, BACKGROUND_CLUSTERS).first() {
+                Some(&(color, share)) if share >= AUTO_BACKGROUND_SHARE => color,
+                _ => get_average_color(target),
+            },
+            Background::Mean => get_average_color(target),
+            Background::Dominant => dominant_colors(&target, BACKGROUND_CLUSTERS)
+                .first()
+                .map(|&(color, _)| color)
+                .unwrap_or_else(|| get_average_color(target.clone())),
+            Background::Color(color) => color,
+            Background::Transparent => Rgba { data: [0, 0, 0, 0] },
+        }
+    }
+}
+
+impl FromStr for Background {
+    type Err = String;
+
+    fn from_str(s: &str) -> ::std::result::Result<Background, String> {
+        match s {
+            "auto" => Ok(Background::Auto),
+            "mean" => Ok(Background::Mean),
+            "dominant" => Ok(Background::Dominant),
+            "transparent" => Ok(Background::Transparent),
+            _ => parse_hex_color(s)
+                .map(Background::Color)
+                .ok_or_else(|| format!("unknown background: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Background {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Background::Auto => write!(f, "auto"),
+            Background::Mean => write!(f, "mean"),
+            Background::Dominant => write!(f, "dominant"),
+            Background::Color(color) => write!(
+                f,
+                "#{:02x}{:02x}{:02x}",
+                color.data[0], color.data[1], color.data[2]
+            ),
+            Background::Transparent => write!(f, "transparent"),
+        }
+    }
+}
+
+/// how the largest allowed shape shrinks over the run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SizeSchedule {
+    Linear,
+    Exponential,
+    /// exponential, held for a quarter of the run at a time
+    Step,
+}
+
+/// number of sizes used by `SizeSchedule::Step`
+const SCHEDULE_STEPS: f32 = 4.0;
+/// final size of a schedule without `min_shape_size`, relative to the canvas
+const SCHEDULE_END: f32 = 0.02;
+
+impl SizeSchedule {
+    /**
+     * @brief      largest allowed shape at `progress`
+     *
+     * @param      start     size at the beginning of the run
+     * @param      end       size at the end of the run
+     * @param      progress  fraction of the run done, in `[0, 1]`
+     */
+    pub fn max_size(&self, start: f32, end: f32, progress: f32) -> f32 {
+        let progress = progress.clamp(0.0, 1.0);
+        match *self {
+            SizeSchedule::Linear => start + (end - start) * progress,
+            SizeSchedule::Exponential => start * (end / start).powf(progress),
+            SizeSchedule::Step => {
+                let step = (progress * SCHEDULE_STEPS)
+                    .floor()
+                    .min(SCHEDULE_STEPS - 1.0);
+                start * (end / start).powf(step / (SCHEDULE_STEPS - 1.0))
+            }
+        }
+    }
+}
+
+impl FromStr for SizeSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> ::std::result::Result<SizeSchedule, String> {
+        match s {
+            "linear" => Ok(SizeSchedule::Linear),
+            "exponential" => Ok(SizeSchedule::Exponential),
+            "step" => Ok(SizeSchedule::Step),
+            _ => Err(format!("unknown size schedule: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for SizeSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SizeSchedule::Linear => write!(f, "linear"),
+            SizeSchedule::Exponential => write!(f, "exponential"),
+            SizeSchedule::Step => write!(f, "step"),
+        }
+    }
+}
+
+/// the condition that ended a simulation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    MaxIterations,
+    TargetScore,
+    TimeLimit,
+    MaxShapes,
+    Stagnation,
+    Cancelled,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StopReason::MaxIterations => write!(f, "maximum number of iterations reached"),
+            StopReason::TargetScore => write!(f, "target score reached"),
+            StopReason::TimeLimit => write!(f, "time limit reached"),
+            StopReason::MaxShapes => write!(f, "maximum number of shapes reached"),
+            StopReason::Stagnation => write!(f, "no improvement for too many generations"),
+            StopReason::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+pub const MAX_ITER: u32 = 1_000_000;
+pub const MAX_GENES: u32 = 10_000;
+pub const MIN_SIZE: u32 = 8;
+pub const MAX_THREADS: u32 = 256;
+pub const MAX_PALETTE_SIZE: u32 = 256;
+
+impl Options {
+    /**
+     * @brief      checks that every option is within its valid range
+     *
+     * @return     an error describing the first invalid option
+     */
+    pub fn validate(&self) -> Result<()> {
+        let shape = self.shape.parse::<Shapes>().map_err(|e| {
+            invalid(format!(
+                "shape: {} (expected rectangle, triangle or stamp)",
+                e
+            ))
+        })?;
+        match self.stamp {
+            Some(ref stamp) if stamp.as_os_str().is_empty() => {
+                return Err(invalid("stamp: path must not be empty".to_owned()));
+            }
+            Some(_) if shape != Shapes::Rectangle && shape != Shapes::Stamp => {
+                return Err(invalid(format!(
+                    "stamp: can't draw {}s with a stamp (expected rectangle or stamp)",
+                    shape
+                )));
+            }
+            None if self.shape == "stamp" => {
+                return Err(invalid("shape: stamp needs a stamp file".to_owned()));
+            }
+            _ => (),
+        }
+        self.metric.parse::<Metrics>().map_err(|e| {
+            invalid(format!(
+                "metric: {} (expected rmse, ssim, ms-ssim or lab)",
+                e
+            ))
+        })?;
+        check_range("max_iter", self.max_iter, 1, MAX_ITER)?;
+        check_range("num_genes", self.num_genes, 1, MAX_GENES)?;
+        check_range("threads", self.threads, 1, MAX_THREADS)?;
+        if !(self.alpha > 0.0 && self.alpha <= 1.0) {
+            return Err(invalid(format!(
+                "alpha: {} is out of range, expected a value greater than 0 and at most 1",
+                self.alpha
+            )));
+        }
+        if let Some(max_size) = self.max_size {
+            check_range("max_size", max_size, MIN_SIZE, u32::MAX)?;
+        }
+        if let Some(ref weights) = self.weights {
+            if weights.as_os_str().is_empty() {
+                return Err(invalid("weights: path must not be empty".to_owned()));
+            }
+        }
+        self.placement
+            .parse::<Placement>()
+            .map_err(|e| invalid(format!("placement: {} (expected uniform or error)", e)))?;
+        let min_shape_size = parse_shape_size("min_shape_size", &self.min_shape_size)?;
+        let max_shape_size = parse_shape_size("max_shape_size", &self.max_shape_size)?;
+        match (min_shape_size, max_shape_size) {
+            (Some(ShapeSize::Pixels(min)), Some(ShapeSize::Pixels(max)))
+            | (Some(ShapeSize::Fraction(min)), Some(ShapeSize::Fraction(max)))
+                if min > max =>
+            {
+                return Err(invalid(
+                    "min_shape_size: must not be larger than max_shape_size".to_owned(),
+                ));
+            }
+            _ => (),
+        }
+        if let Some(max_aspect_ratio) = self.max_aspect_ratio {
+            if self.stamp.is_some() {
+                return Err(invalid(
+                    "max_aspect_ratio: can't be used with a stamp, which keeps its own proportions"
+                        .to_owned(),
+                ));
+            }
+            let min_aspect_ratio = shape.min_aspect_ratio();
+            if max_aspect_ratio.is_nan() || max_aspect_ratio < min_aspect_ratio {
+                return Err(invalid(format!(
+                    "max_aspect_ratio: {} is out of range, expected a value of at least {} for {}s",
+                    max_aspect_ratio, min_aspect_ratio, shape
+                )));
+            }
+        }
+        self.background.parse::<Background>().map_err(|e| {
+            invalid(format!(
+                "background: {} (expected auto, mean, dominant, transparent or #rrggbb)",
+                e
+            ))
+        })?;
+        let gradients = gradients(self)?;
+        if style(self)? == Style::Stroke && !gradients.is_empty() {
+            return Err(invalid(
+                "gradients: outlined shapes have no fill to shade".to_owned(),
+            ));
+        }
+        let color_mode = color_mode(self)?;
+        if color_mode != ColorMode::Color && (self.palette.is_some() || self.palette_size.is_some())
+        {
+            return Err(invalid(format!(
+                "color_mode: {} cannot be combined with a palette",
+                color_mode
+            )));
+        }
+        if let ColorMode::Mono(_) = color_mode {
+            if self.background == "transparent" {
+                return Err(invalid(
+                    "color_mode: mono needs an opaque background as paper".to_owned(),
+                ));
+            }
+        }
+        if let Some(ref palette) = self.palette {
+            if palette.as_os_str().is_empty() {
+                return Err(invalid("palette: path must not be empty".to_owned()));
+            }
+        }
+        if let Some(palette_size) = self.palette_size {
+            check_range("palette_size", palette_size, 1, MAX_PALETTE_SIZE)?;
+            if self.palette.is_some() {
+                return Err(invalid(
+                    "palette_size: cannot be combined with a palette file".to_owned(),
+                ));
+            }
+        }
+        if let Some(ref size_schedule) = self.size_schedule {
+            size_schedule.parse::<SizeSchedule>().map_err(|e| {
+                invalid(format!(
+                    "size_schedule: {} (expected linear, exponential or step)",
+                    e
+                ))
+            })?;
+        }
+        if let Some(ref auto_weights) = self.auto_weights {
+            auto_weights.parse::<AutoWeights>().map_err(|e| {
+                invalid(format!("auto_weights: {} (expected edges or saliency)", e))
+            })?;
+            if self.weights.is_some() {
+                return Err(invalid(
+                    "auto_weights: cannot be combined with a weights mask".to_owned(),
+                ));
+            }
+        }
+        if self.output.as_os_str().is_empty() {
+            return Err(invalid("output: path must not be empty".to_owned()));
+        }
+        if let Some(target_score) = self.target_score {
+            if target_score.is_nan() || target_score < 0.0 {
+                return Err(invalid(format!(
+                    "target_score: {} must not be negative",
+                    target_score
+                )));
+            }
+        }
+        if self.time_limit == Some(0) {
+            return Err(invalid(
+                "time_limit: must be at least one second".to_owned(),
+            ));
+        }
+        if let Some(max_shapes) = self.max_shapes {
+            check_range("max_shapes", max_shapes, 1, u32::MAX)?;
+        }
+        if let Some(max_stagnation) = self.max_stagnation {
+            check_range("max_stagnation", max_stagnation, 1, u32::MAX)?;
+        }
+        Ok(())
+    }
+
+    /**
+     * @brief      checks the stop conditions other than `max_iter`
+     *
+     * @param      score       score of the whole image
+     * @param      shapes      number of shapes in the result
+     * @param      stagnation  generations since the last improvement
+     * @param      elapsed     time since the simulation started
+     */
+    fn stop_reason(
+        &self,
+        score: f32,
+        shapes: u32,
+        stagnation: u32,
+        elapsed: Duration,
+    ) -> Option<StopReason> {
+        if self.target_score.is_some_and(|t| score <= t) {
+            Some(StopReason::TargetScore)
+        } else if self.max_shapes.is_some_and(|m| shapes >= m) {
+            Some(StopReason::MaxShapes)
+        } else if self.max_stagnation.is_some_and(|m| stagnation >= m) {
+            Some(StopReason::Stagnation)
+        } else if self
+            .time_limit
+            .is_some_and(|t| elapsed >= Duration::from_secs(t))
+        {
+            Some(StopReason::TimeLimit)
+        } else {
+            None
+        }
+    }
+}
+
+fn parse_shape_size(name: &str, size: &Option<String>) -> Result<Option<ShapeSize>> {
+    match *size {
+        Some(ref size) => size
+            .parse()
+            .map(Some)
+            .map_err(|e| invalid(format!("{}: {}", name, e))),
+        None => Ok(None),
+    }
+}
+
+fn invalid(msg: String) -> Error {
+    Error::InvalidOptions(msg)
+}
+
+fn check_range(name: &str, value: u32, min: u32, max: u32) -> Result<()> {
+    if value < min || value > max {
+        return Err(invalid(format!(
+            "{}: {} is out of range, expected a value between {} and {}",
+            name, value, min, max
+        )));
+    }
+    Ok(())
+}
+
 impl Default for Options {
     fn default() -> Self {
         Options {
-            pop_size: 100,
             shape: "rectangle".to_owned(),
+            stamp: None,
             max_iter: 200,
             num_genes: 100,
+            alpha: 0.7,
+            metric: "rmse".to_owned(),
+            weights: None,
+            auto_weights: None,
+            placement: "uniform".to_owned(),
+            min_shape_size: None,
+            max_shape_size: None,
+            max_aspect_ratio: None,
+            size_schedule: None,
+            background: "auto".to_owned(),
+            gradients: None,
+            style: "fill".to_owned(),
+            color_mode: "color".to_owned(),
+            inks: None,
+            palette: None,
+            palette_size: None,
+            seed: None,
+            max_size: None,
+            output: PathBuf::from("result.png"),
+            threads: 4,
+            target_score: None,
+            time_limit: None,
+            max_shapes: None,
+            max_stagnation: None,
             render_debug_rasters: false,
         }
     }
 }
 
-pub fn run(target: Arc<DynamicImage>, options: Options) -> Result<()> {
-    info!(
-        "running simulation, shape: {}, max_generations: {}, max_genes: {}",
-        options.shape, options.max_iter, options.num_genes
-    );
+/// what happened during a single `Simulation::step`
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// the best mutation improved the image and its polygon was added
+    Accepted {
+        iteration: u32,
+        polygon: Polygon,
+        score: f32,
+    },
+    /// none of the mutations improved the image
+    Rejected { iteration: u32, score: f32 },
+    /// a stop condition was met, further steps do nothing
+    Stopped(StopReason),
+}
 
-    // let mut population = make_population(options.pop_size, target);
-    let avg_color = get_average_color(target.clone());
-    let (width, height) = target.dimensions();
-    let mut result_gene = GImage::new(1, target.clone(), avg_color, width, height);
-    let width = width as f32;
-    let height = height as f32;
-
-    let shape = match options.shape.as_str() {
-        "rectangle" => Shapes::Rectangle,
-        "triangle" => Shapes::Triangle,
-        _ => Shapes::Rectangle,
-    };
+/**
+ * Shared flag used to stop a simulation from another thread. The simulation
+ * checks it between generations and returns the partial result.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// called with every event and the individual built so far
+pub type Observer = Box<dyn FnMut(&Event, &GImage) + Send>;
+
+/**
+ * Step-wise driver of the optimizer. Every call to `step` runs one
+ * generation, the simulation can be paused between steps and the current
+ * individual inspected. It is also an `Iterator` over the events of the
+ * remaining generations.
+ */
+pub struct Simulation {
+    target: Arc<DynamicImage>,
+    options: Options,
+    shape: Shapes,
+    /// the path new shapes are drawn with when `shape` is `Shapes::Stamp`
+    stamp: Option<Arc<Stamp>>,
+    rng: StdRng,
+    pool: Pool,
+    result_gene: GImage,
+    scores: Vec<f32>,
+    iteration: u32,
+    stagnation: u32,
+    started: Instant,
+    /// size of the original target, the result is exported at it
+    target_size: (u32, u32),
+    stop_reason: Option<StopReason>,
+    /// set once a step of the iterator failed, it yields nothing afterwards
+    failed: bool,
+    observer: Option<Observer>,
+    cancel_token: Option<CancelToken>,
+    /// biases the placement of new shapes, also used by the metric
+    weights: Option<Arc<Weights>>,
+    placement: Placement,
+    /// opacity of every target pixel, `None` for opaque targets
+    visibility: Option<Vec<f32>>,
+    /// the weights, or the whole canvas, limited to the visible pixels of a
+    /// transparent target
+    visible_weights: Option<Weights>,
+    /// size and form limits of new shapes
+    limits: ShapeLimits,
+    size_schedule: Option<SizeSchedule>,
+    /// sampler over the error of the current image, `None` when it needs
+    /// to be rebuilt
+    error_map: Option<Weights>,
+}
+
+impl Simulation {
+    pub fn new(target: Arc<DynamicImage>, options: Options) -> Result<Simulation> {
+        options.validate()?;
+        let (target, target_size) = working_image(target, &options);
+
+        let background: Background = options.background.parse().map_err(Error::InvalidOptions)?;
+        let color_mode = color_mode(&options)?;
+        let mut background_color = match (color_mode, background) {
+            // the ink goes on white paper unless told otherwise
+            (ColorMode::Mono(_), Background::Color(paper)) => paper,
+            (ColorMode::Mono(_), _) => Rgba {
+                data: [255, 255, 255, 255],
+            },
+            _ => background.color(target.clone()),
+        };
+        background_color = color_mode.background(background_color);
+        let palette = palette(&options, &target)?;
+        match (&palette, background) {
+            (&Some(ref palette), Background::Auto)
+            | (&Some(ref palette), Background::Mean)
+            | (&Some(ref palette), Background::Dominant)
+                if background_color.data[3] > 0 =>
+            {
+                background_color = palette.nearest(&background_color);
+            }
+            _ => (),
+        }
+        info!("{} background: {:?}", background, background_color.data);
+        let target = color_target(color_mode, target, background_color);
+        let (width, height) = target.dimensions();
+        let result_gene = GImage::new(1, target.clone(), background_color, width, height);
+
+        Simulation::from_gene(
+            target,
+            result_gene,
+            Vec::new(),
+            palette,
+            None,
+            options,
+            target_size,
+        )
+    }
+
+    /// continue optimizing the polygons of `model`
+    pub fn resume(target: Arc<DynamicImage>, model: Model, options: Options) -> Result<Simulation> {
+        options.validate()?;
+        let (target, target_size) = working_image(target, &options);
 
-    let mut evolutions = 0;
-    for i in 0..options.max_iter {
+        let (width, height) = target.dimensions();
+        if (width, height) != (model.width, model.height) {
+            return Err(Error::SizeMismatch {
+                expected: (width, height),
+                found: (model.width, model.height),
+            });
+        }
+        info!("resuming from {} polygons", model.polygons.len());
+
+        let scores = model.scores.clone();
+        let stamp = model
+            .polygons
+            .iter()
+            .filter_map(|p| p.stamp())
+            .next()
+            .cloned();
+        let target = color_target(color_mode(&options)?, target, model.background);
+        let result_gene = GImage::from_model(1, target.clone(), model);
+        let palette = palette(&options, &target)?;
+        Simulation::from_gene(
+            target,
+            result_gene,
+            scores,
+            palette,
+            stamp,
+            options,
+            target_size,
+        )
+    }
+
+    /// `model_stamp` is the stamp of the resumed polygons, it keeps being
+    /// drawn unless `options` name another stamp or triangles
+    fn from_gene(
+        target: Arc<DynamicImage>,
+        mut result_gene: GImage,
+        mut scores: Vec<f32>,
+        palette: Option<Arc<Palette>>,
+        model_stamp: Option<Arc<Stamp>>,
+        options: Options,
+        target_size: (u32, u32),
+    ) -> Result<Simulation> {
+        info!(
+            "running simulation, shape: {}, metric: {}, max_generations: {}, max_genes: {}",
+            options.shape, options.metric, options.max_iter, options.num_genes
+        );
+
+        let mut shape: Shapes = options.shape.parse().map_err(Error::InvalidOptions)?;
+        let stamp = match (&options.stamp, model_stamp) {
+            (Some(path), _) => {
+                info!("drawing shapes with the path of {}", path.display());
+                shape = Shapes::Stamp;
+                Some(Arc::new(Stamp::load(path)?))
+            }
+            (None, Some(stamp)) if shape != Shapes::Triangle => {
+                info!("drawing shapes with the path of the resumed model");
+                shape = Shapes::Stamp;
+                Some(stamp)
+            }
+            _ => None,
+        };
+        let metric: Metrics = options.metric.parse().map_err(Error::InvalidOptions)?;
+        let placement: Placement = options.placement.parse().map_err(Error::InvalidOptions)?;
+        let size_schedule: Option<SizeSchedule> = match options.size_schedule {
+            Some(ref s) => Some(s.parse().map_err(Error::InvalidOptions)?),
+            None => None,
+        };
+        let rng = match options.seed {
+            Some(seed) => StdRng::from_seed(&[seed as usize][..]),
+            None => StdRng::new()?,
+        };
+        result_gene.set_alpha(options.alpha);
+        result_gene.set_metric(metric);
+        let weights = match (&options.weights, &options.auto_weights) {
+            (Some(path), _) => {
+                let (width, height) = target.dimensions();
+                info!("weighting errors with {}", path.display());
+                Some(Arc::new(Weights::load(path, width, height)?))
+            }
+            (_, Some(auto)) => {
+                let auto: AutoWeights = auto.parse().map_err(Error::InvalidOptions)?;
+                info!("weighting errors with the {} of the target", auto);
+                Some(Arc::new(Weights::auto(auto, &target)))
+            }
+            _ => None,
+        };
+        result_gene.set_weights(weights.clone());
+        result_gene.set_palette(palette);
+        result_gene.set_color_mode(color_mode(&options)?);
+        result_gene.set_gradients(gradients(&options)?);
+        result_gene.set_style(style(&options)?);
+
+        // shapes are only placed over the visible part of the target
+        let visibility = opacity(&target);
+        let visible_weights = visibility.as_ref().map(|visibility| {
+            let (width, height) = target.dimensions();
+            let weights = weights.as_deref();
+            Weights::from_data(width, height, visible(visibility, weights, width))
+        });
+
+        let limits = shape_limits(&options, &target)?;
+        result_gene.set_limits(limits);
+
+        let score = result_gene.fitness_full()?;
+        if scores.is_empty() {
+            scores.push(score);
+        }
+
+        Ok(Simulation {
+            target: target,
+            shape: shape,
+            stamp: stamp,
+            rng: rng,
+            pool: Pool::new(options.threads),
+            result_gene: result_gene,
+            scores: scores,
+            iteration: 0,
+            stagnation: 0,
+            started: Instant::now(),
+            target_size: target_size,
+            stop_reason: None,
+            failed: false,
+            observer: None,
+            cancel_token: None,
+            weights: weights,
+            placement: placement,
+            visibility: visibility,
+            visible_weights: visible_weights,
+            limits: limits,
+            size_schedule: size_schedule,
+            error_map: None,
+            options: options,
+        })
+    }
+
+    /// the individual built so far
+    pub fn current(&self) -> &GImage {
+        &self.result_gene
+    }
+
+    /// score of the whole image, lower is better
+    pub fn score(&self) -> f32 {
+        // there is always at least the score of the initial image
+        *self.scores.last().unwrap()
+    }
+
+    /// number of generations run so far
+    pub fn iteration(&self) -> u32 {
+        self.iteration
+    }
+
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        self.stop_reason
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.stop_reason.is_some()
+    }
+
+    /// registers a callback receiving every event of the following steps
+    pub fn set_observer<F>(&mut self, observer: F)
+    where
+        F: FnMut(&Event, &GImage) + Send + 'static,
+    {
+        self.observer = Some(Box::new(observer));
+    }
+
+    /// the simulation stops with `StopReason::Cancelled` once `token` is
+    /// cancelled
+    pub fn set_cancel_token(&mut self, token: CancelToken) {
+        self.cancel_token = Some(token);
+    }
+
+    /**
+     * @brief      the shape limits of the next generation, with the largest
+     *             size shrunk by the schedule according to the number of
+     *             shapes relative to `max_shapes`, or without it the number
+     *             of generations relative to `max_iter`
+     */
+    fn current_limits(&self) -> ShapeLimits {
+        let schedule = match self.size_schedule {
+            Some(schedule) => schedule,
+            None => return self.limits,
+        };
+        let (width, height) = self.target.dimensions();
+        let canvas = width.max(height) as f32;
+        let start = self.limits.max_size.unwrap_or(canvas);
+        let end = self
+            .limits
+            .min_size
+            .unwrap_or(canvas * SCHEDULE_END)
+            .min(start);
+        // not every generation adds a shape, without a shape count to
+        // reach the schedule runs over the generations
+        let progress = match self.options.max_shapes {
+            Some(max_shapes) => self.result_gene.num_polygons() as f32 / max_shapes as f32,
+            None => self.iteration as f32 / self.options.max_iter as f32,
+        };
+        ShapeLimits {
+            max_size: Some(schedule.max_size(start, end, progress)),
+            ..self.limits
+        }
+    }
+
+    /// snapshot of the current state as a model
+    pub fn model(&self) -> Model {
+        let mut model = self.result_gene.to_model();
+        model.scores = self.scores.clone();
+        model.stop_reason = self.stop_reason;
+        model.target_size = self.target_size;
+        model
+    }
+
+    /**
+     * @brief      runs one generation: generates a candidate polygon, scores
+     *             its mutations and adds the best one if it improves the image
+     *
+     * @return     the outcome of the generation, `Event::Stopped` once a stop
+     *             condition is met
+     */
+    pub fn step(&mut self) -> Result<Event> {
+        if let Some(reason) = self.stop_reason {
+            return Ok(Event::Stopped(reason));
+        }
+        let event = self.generation()?;
+        if let Some(ref mut observer) = self.observer {
+            observer(&event, &self.result_gene);
+        }
+        Ok(event)
+    }
+
+    fn generation(&mut self) -> Result<Event> {
+        let cancelled = self
+            .cancel_token
+            .as_ref()
+            .is_some_and(|token| token.is_cancelled());
+        // the conditions the user asked for take precedence over the
+        // iteration limit, which always applies
+        let reason = if cancelled {
+            Some(StopReason::Cancelled)
+        } else {
+            self.options
+                .stop_reason(
+                    self.score(),
+                    self.result_gene.num_polygons() as u32,
+                    self.stagnation,
+                    self.started.elapsed(),
+                )
+                .or(if self.iteration >= self.options.max_iter {
+                    Some(StopReason::MaxIterations)
+                } else {
+                    None
+                })
+        };
+        if let Some(reason) = reason {
+            info!("finished: {}, score: {}", reason, self.score());
+            self.stop_reason = Some(reason);
+            return Ok(Event::Stopped(reason));
+        }
+
+        let i = self.iteration;
+        self.iteration += 1;
         debug!("generation {}", i);
 
-        // generate candidate
-        let new_shape = Polygon::new(shape, width, height);
+        let current = self.result_gene.as_rgba_img()?;
+
+        let limits = self.current_limits();
+        self.result_gene.set_limits(limits);
+        let new_shape = self.candidate(&current, &limits);
         let mut mutations: Vec<GImage> = Vec::new();
-        for j in 0..options.num_genes {
-            let new_gene = result_gene.mutate(new_shape.clone(), j);
+        for j in 0..self.options.num_genes {
+            let new_gene = self.result_gene.mutate(&mut self.rng, new_shape.clone(), j);
             mutations.push(new_gene);
         }
 
-        let mut best_fitness = Arc::new(RwLock::new(10_000_000.0));
-        let mut best_id = Arc::new(RwLock::new(0));
-        let mut pool = Pool::new(4);
-        pool.scoped(|scoped| {
-            for gene in &mutations {
-                let best_fitness = best_fitness.clone();
-                let best_id = best_id.clone();
-
+        // every job scores into its own slot, the winner is picked once all
+        // of them are done
+        let mut fitnesses = vec![f64::INFINITY; mutations.len()];
+        self.pool.scoped(|scoped| {
+            for (gene, fitness) in mutations.iter().zip(fitnesses.iter_mut()) {
+                let current = &current;
                 scoped.execute(move || {
-                    let fitness = gene.fitness_mutation();
-
-                    if fitness < *best_fitness.read().unwrap() {
-                        let mut w = best_fitness.write().unwrap();
-                        *w = fitness;
-                        let mut w = best_id.write().unwrap();
-                        *w = gene.id();
-                    }
+                    *fitness = gene.fitness_mutation(current);
                 });
             }
         });
 
-        let winner_gene = mutations
+        // the mutations are ordered by id, ties go to the lowest one
+        let (best_fitness, winner_gene) = mutations
             .iter()
-            .find(|ref mutation| mutation.id() == *best_id.read().unwrap())
-            .ok_or(format_err!(
-                "Unexpected end of mutation list, unable to find winner gene"
-            ))?;
+            .zip(fitnesses)
+            .filter(|&(_, fitness)| !fitness.is_nan())
+            .fold(
+                None,
+                |best: Option<(f64, &GImage)>, (gene, fitness)| match best {
+                    Some((best_fitness, _)) if best_fitness <= fitness => best,
+                    _ => Some((fitness, gene)),
+                },
+            )
+            .ok_or(Error::NoWinner)?;
+        let polygon = winner_gene.get_last_polygon().ok_or(Error::NoWinner)?;
 
-        debug!("we have a winner: {}", winner_gene.get_last_polygon().svg());
+        debug!("we have a winner: {}", polygon.svg(0));
 
-        let mutation_area_current_fitness = image_area_diff(
-            target.clone(),
-            &result_gene.as_rgba_img()?,
-            &winner_gene.mutation_area(),
-        );
-
-        // no need to be mutex anymore
-        let best_fitness = *best_fitness.read().unwrap();
-        if mutation_area_current_fitness > best_fitness {
+        // the fitness is the change of the whole image error, so only a
+        // negative value makes the image better
+        if best_fitness < 0.0 {
+            self.result_gene.add_polygon(polygon.clone());
+            self.error_map = None;
+            let score = self.result_gene.fitness_full()?;
             info!(
-                "we are evolving! :) current score: {}, mutation score: {}",
-                mutation_area_current_fitness, best_fitness
+                "we are evolving! :) score: {} -> {}, error change: {}",
+                self.score(),
+                score,
+                best_fitness
+            );
+            self.scores.push(score);
+            self.stagnation = 0;
+            Ok(Event::Accepted {
+                iteration: i,
+                polygon: polygon,
+                score: score,
+            })
+        } else {
+            warn!(
+                "mutation did not improve gene :(\nscore: {}, error change: {}",
+                self.score(),
+                best_fitness
             );
-            result_gene.add_polygon(winner_gene.get_last_polygon());
+            self.stagnation += 1;
+            Ok(Event::Rejected {
+                iteration: i,
+                score: self.score(),
+            })
+        }
+    }
+
+    /**
+     * @brief      generates the shape of the next generation within `limits`,
+     *             centered according to the placement
+     *
+     * @param      current  the raster of the current image
+     */
+    fn candidate(&mut self, current: &RgbaImage, limits: &ShapeLimits) -> Polygon {
+        let (width, height) = self.target.dimensions();
+        let (width, height) = (width as f32, height as f32);
+        let mut new_shape = match self.stamp {
+            Some(ref stamp) => Polygon::generate_stamp(
+                &mut self.rng,
+                stamp.clone(),
+                width,
+                height,
+                limits.max_size,
+            ),
+            None => Polygon::generate(&mut self.rng, self.shape, width, height, limits.max_size),
+        };
+        new_shape.constrain(limits);
+        if let Some(center) = self.placement_center(current) {
+            new_shape.move_to(&center);
+        }
+        new_shape
+    }
 
-            if options.render_debug_rasters {
-                let fname = format!("./tmp/evolution-{}.png", evolutions);
-                debug!("saving raster of the current generation: {}", fname);
-                result_gene.save_raster(Path::new(&fname))?;
+    /**
+     * @brief      where the next shape is centered according to the
+     *             placement, `None` keeps the position it was generated at
+     *
+     * @param      current  the raster of the current image
+     */
+    fn placement_center(&mut self, current: &RgbaImage) -> Option<Point> {
+        match self.placement {
+            Placement::Error => {
+                if self.error_map.is_none() {
+                    let (width, height) = self.target.dimensions();
+                    let weights = self.weights.as_deref();
+                    let mut errors = pixel_errors(self.target.clone(), current, weights);
+                    if let Some(ref visibility) = self.visibility {
+                        for (error, v) in errors.iter_mut().zip(visibility) {
+                            *error *= v;
+                        }
+                    }
+                    self.error_map = Some(Weights::from_data(width, height, errors));
+                }
+                let rng = &mut self.rng;
+                self.error_map
+                    .as_ref()
+                    .map(|error_map| error_map.sample(rng))
+            }
+            Placement::Uniform => {
+                let weights = match self.visible_weights {
+                    Some(ref visible) => Some(visible),
+                    None => self.weights.as_deref(),
+                };
+                let rng = &mut self.rng;
+                weights.map(|weights| weights.sample(rng))
             }
+        }
+    }
 
-            evolutions += 1;
-        } else {
-            warn!(
-                "mutation did not improve gene :(\ncurrent score: {}, mutation score: {}",
-                mutation_area_current_fitness, best_fitness
+    /// runs the remaining generations and returns the resulting model
+    pub fn finish(mut self) -> Result<Model> {
+        while !self.is_finished() {
+            self.step()?;
+        }
+        Ok(self.model())
+    }
+}
+
+impl Iterator for Simulation {
+    type Item = Result<Event>;
+
+    /// yields one event per generation, the last one is `Event::Stopped` or
+    /// the first error
+    fn next(&mut self) -> Option<Result<Event>> {
+        if self.is_finished() || self.failed {
+            return None;
+        }
+        let event = self.step();
+        self.failed = event.is_err();
+        Some(event)
+    }
+}
+
+pub fn run(target: Arc<DynamicImage>, options: Options) -> Result<Model> {
+    Simulation::new(target, options)?.finish()
+}
+
+pub fn resume(target: Arc<DynamicImage>, model: Model, options: Options) -> Result<Model> {
+    Simulation::resume(target, model, options)?.finish()
+}
+
+/// `weights`, or 1, times the opacity of every pixel, row by row
+fn visible(visibility: &[f32], weights: Option<&Weights>, width: u32) -> Vec<f32> {
+    visibility
+        .iter()
+        .enumerate()
+        .map(|(i, v)| {
+            let (x, y) = (i as u32 % width, i as u32 / width);
+            v * weight_at(weights, x, y) as f32
+        })
+        .collect()
+}
+
+/// the shape limits of `options` in pixels of the working image
+fn shape_limits(options: &Options, target: &DynamicImage) -> Result<ShapeLimits> {
+    let (width, height) = target.dimensions();
+    let canvas = width.max(height) as f32;
+    let to_pixels = |size: Option<ShapeSize>| size.map(|s| s.to_pixels(canvas));
+    let limits = ShapeLimits {
+        min_size: to_pixels(parse_shape_size("min_shape_size", &options.min_shape_size)?),
+        max_size: to_pixels(parse_shape_size("max_shape_size", &options.max_shape_size)?),
+        max_aspect_ratio: options.max_aspect_ratio,
+    };
+    if let (Some(min), Some(max)) = (limits.min_size, limits.max_size) {
+        if min > max {
+            return Err(invalid(format!(
+                "min_shape_size: {} pixels is larger than max_shape_size: {} pixels",
+                min, max
+            )));
+        }
+    }
+    Ok(limits)
+}
+
+fn gradients(options: &Options) -> Result<Vec<GradientKind>> {
+    let gradients = match options.gradients {
+        Some(ref gradients) => gradients,
+        None => return Ok(Vec::new()),
+    };
+    let mut kinds = Vec::new();
+    for kind in gradients.split(',') {
+        let kind: GradientKind = kind.trim().parse().map_err(|e| {
+            invalid(format!(
+                "gradients: {} (expected linear, radial or both)",
+                e
+            ))
+        })?;
+        if !kinds.contains(&kind) {
+            kinds.push(kind);
+        }
+    }
+    Ok(kinds)
+}
+
+fn style(options: &Options) -> Result<Style> {
+    options
+        .style
+        .parse()
+        .map_err(|e| invalid(format!("style: {} (expected fill, stroke or both)", e)))
+}
+
+fn color_mode(options: &Options) -> Result<ColorMode> {
+    ColorMode::parse(&options.color_mode, options.inks.as_deref())
+        .map_err(|e| invalid(format!("color_mode: {}", e)))
+}
+
+/// the target as seen by `color_mode`, the shapes are scored against it
+fn color_target(
+    color_mode: ColorMode,
+    target: Arc<DynamicImage>,
+    background: Rgba<u8>,
+) -> Arc<DynamicImage> {
+    match color_mode {
+        ColorMode::Color => target,
+        _ => {
+            info!("scoring against the {} target", color_mode);
+            Arc::new(color_mode.target(&target, background))
+        }
+    }
+}
+
+/// the palette of `options`, loaded from a file or extracted from the target
+fn palette(options: &Options, target: &DynamicImage) -> Result<Option<Arc<Palette>>> {
+    let palette = match (options.palette.as_ref(), options.palette_size) {
+        (Some(path), _) => Palette::load(path)?,
+        (_, Some(k)) => Palette::from_target(target, k as usize),
+        _ => return Ok(None),
+    };
+    info!("painting with {} colors", palette.colors().len());
+    Ok(Some(Arc::new(palette)))
+}
+
+/**
+ * @brief      downscales the target according to `Options::max_size`
+ *
+ * @return     the working image and the size of the original target, the
+ *             result is rendered back at it
+ *             at the original size
+ */
+fn working_image(target: Arc<DynamicImage>, options: &Options) -> (Arc<DynamicImage>, (u32, u32)) {
+    let (width, height) = target.dimensions();
+    match options.max_size {
+        Some(max_size) if width.max(height) > max_size => {
+            let resized = target.resize(max_size, max_size, FilterType::Triangle);
+            info!(
+                "working on a {}x{} copy of the target",
+                resized.width(),
+                resized.height()
             );
+            (Arc::new(resized), (width, height))
         }
+        _ => (target, (width, height)),
     }
-    debug!("finished.");
+}
 
-    result_gene.save_raster(Path::new("result.png"))?;
-    info!("result saved to result.png");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image;
+    use rand::thread_rng;
+    use std::env;
+    use std::fs;
 
-    Ok(())
+    fn get_test_image() -> Arc<DynamicImage> {
+        let img = RgbaImage::from_pixel(
+            64,
+            64,
+            Rgba {
+                data: [200, 100, 50, 255],
+            },
+        );
+        Arc::new(DynamicImage::ImageRgba8(img))
+    }
+
+    #[test]
+    fn default_options_should_be_valid() {
+        assert!(Options::default().validate().is_ok());
+    }
+
+    #[test]
+    fn should_reject_invalid_options() {
+        let mut o = Options::default();
+        o.shape = "circle".to_owned();
+        assert!(o.validate().is_err());
+
+        let mut o = Options::default();
+        o.shape = "stamp".to_owned();
+        assert!(o.validate().is_err());
+        o.stamp = Some(PathBuf::from("heart.svg"));
+        assert!(o.validate().is_ok());
+        o.stamp = Some(PathBuf::new());
+        assert!(o.validate().is_err());
+        o.stamp = Some(PathBuf::from("heart.svg"));
+        o.shape = "rectangle".to_owned();
+        assert!(o.validate().is_ok());
+        o.shape = "triangle".to_owned();
+        assert!(o.validate().is_err());
+
+        let mut o = Options::default();
+        o.num_genes = 0;
+        assert!(o.validate().is_err());
+
+        let mut o = Options::default();
+        o.max_iter = MAX_ITER + 1;
+        assert!(o.validate().is_err());
+
+        let mut o = Options::default();
+        o.max_stagnation = Some(0);
+        assert!(o.validate().is_err());
+
+        let mut o = Options::default();
+        o.metric = "psnr".to_owned();
+        assert!(o.validate().is_err());
+
+        let mut o = Options::default();
+        o.auto_weights = Some("edges".to_owned());
+        assert!(o.validate().is_ok());
+        o.weights = Some(PathBuf::from("mask.png"));
+        assert!(o.validate().is_err());
+
+        let mut o = Options::default();
+        o.placement = "center".to_owned();
+        assert!(o.validate().is_err());
+
+        let mut o = Options::default();
+        o.min_shape_size = Some("4".to_owned());
+        o.max_shape_size = Some("25%".to_owned());
+        o.max_aspect_ratio = Some(3.0);
+        assert!(o.validate().is_ok());
+        o.min_shape_size = Some("30%".to_owned());
+        assert!(o.validate().is_err());
+        o.min_shape_size = Some("-4".to_owned());
+        assert!(o.validate().is_err());
+
+        let mut o = Options::default();
+        o.max_aspect_ratio = Some(0.5);
+        assert!(o.validate().is_err());
+        o.max_aspect_ratio = Some(1.1);
+        assert!(o.validate().is_ok());
+        o.shape = "triangle".to_owned();
+        assert!(o.validate().is_err());
+        o.max_aspect_ratio = Some(1.2);
+        assert!(o.validate().is_ok());
+        o.shape = "rectangle".to_owned();
+        o.stamp = Some(PathBuf::from("stamp.svg"));
+        assert!(o.validate().is_err());
+
+        let mut o = Options::default();
+        o.size_schedule = Some("cubic".to_owned());
+        assert!(o.validate().is_err());
+
+        let mut o = Options::default();
+        o.palette_size = Some(0);
+        assert!(o.validate().is_err());
+        o.palette_size = Some(8);
+        assert!(o.validate().is_ok());
+        o.palette = Some(PathBuf::from("brand.gpl"));
+        assert!(o.validate().is_err());
+
+        let mut o = Options::default();
+        o.gradients = Some("radial, linear".to_owned());
+        assert!(o.validate().is_ok());
+        o.gradients = Some("conic".to_owned());
+        assert!(o.validate().is_err());
+
+        let mut o = Options::default();
+        o.style = "both".to_owned();
+        o.gradients = Some("linear".to_owned());
+        assert!(o.validate().is_ok());
+        o.style = "stroke".to_owned();
+        assert!(o.validate().is_err());
+        o.gradients = None;
+        assert!(o.validate().is_ok());
+        o.style = "dashed".to_owned();
+        assert!(o.validate().is_err());
+
+        let mut o = Options::default();
+        o.color_mode = "duotone".to_owned();
+        o.inks = Some("#000000,#ff0000".to_owned());
+        assert!(o.validate().is_ok());
+        o.inks = Some("#000000".to_owned());
+        assert!(o.validate().is_err());
+        o.color_mode = "mono".to_owned();
+        assert!(o.validate().is_ok());
+        o.palette_size = Some(4);
+        assert!(o.validate().is_err());
+        o.palette_size = None;
+        o.background = "transparent".to_owned();
+        assert!(o.validate().is_err());
+
+        let mut o = Options::default();
+        o.background = "#12ab3f".to_owned();
+        assert!(o.validate().is_ok());
+        o.background = "#12ab3".to_owned();
+        assert!(o.validate().is_err());
+        o.background = "black".to_owned();
+        assert!(o.validate().is_err());
+    }
+
+    #[test]
+    fn should_report_stop_reason() {
+        let mut o = Options::default();
+        let second = Duration::from_secs(1);
+        assert_eq!(o.stop_reason(10.0, 5, 5, second), None);
+
+        o.target_score = Some(12.0);
+        assert_eq!(
+            o.stop_reason(10.0, 5, 5, second),
+            Some(StopReason::TargetScore)
+        );
+
+        let mut o = Options::default();
+        o.max_shapes = Some(5);
+        assert_eq!(
+            o.stop_reason(10.0, 5, 0, second),
+            Some(StopReason::MaxShapes)
+        );
+
+        let mut o = Options::default();
+        o.max_stagnation = Some(3);
+        assert_eq!(o.stop_reason(10.0, 5, 2, second), None);
+        assert_eq!(
+            o.stop_reason(10.0, 5, 3, second),
+            Some(StopReason::Stagnation)
+        );
+
+        let mut o = Options::default();
+        o.time_limit = Some(1);
+        assert_eq!(
+            o.stop_reason(10.0, 5, 0, second),
+            Some(StopReason::TimeLimit)
+        );
+    }
+
+    #[test]
+    fn should_step_through_simulation() {
+        let mut o = Options::default();
+        o.max_iter = 3;
+        o.num_genes = 2;
+        o.seed = Some(1);
+        let mut simulation = Simulation::new(get_test_image(), o).unwrap();
+
+        match simulation.step().unwrap() {
+            Event::Accepted { iteration, .. } | Event::Rejected { iteration, .. } => {
+                assert_eq!(iteration, 0)
+            }
+            Event::Stopped(_) => panic!("stopped too early"),
+        }
+        assert_eq!(simulation.iteration(), 1);
+        assert!(!simulation.is_finished());
+
+        let events: Vec<Event> = simulation.by_ref().map(|e| e.unwrap()).collect();
+        assert_eq!(events.len(), 3);
+        match events[2] {
+            Event::Stopped(reason) => assert_eq!(reason, StopReason::MaxIterations),
+            _ => panic!("expected the simulation to stop"),
+        }
+        assert!(simulation.is_finished());
+        assert_eq!(simulation.iteration(), 3);
+
+        let model = simulation.model();
+        assert_eq!(model.scores.len(), model.polygons.len() + 1);
+    }
+
+    #[test]
+    fn should_export_at_the_size_of_the_target() {
+        let img = RgbaImage::from_pixel(
+            404,
+            377,
+            Rgba {
+                data: [200, 100, 50, 255],
+            },
+        );
+        let mut o = Options::default();
+        o.max_iter = 1;
+        o.num_genes = 2;
+        o.seed = Some(1);
+        o.max_size = Some(64);
+        let model = run(Arc::new(DynamicImage::ImageRgba8(img)), o).unwrap();
+        assert!(model.width <= 64 && model.height <= 64);
+        assert_eq!(model.target_size, (404, 377));
+
+        let path = env::temp_dir().join("geoshaper-export-size.png");
+        model.export(&path).unwrap();
+        let exported = image::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("shapes")).unwrap();
+        assert_eq!(exported.dimensions(), (404, 377));
+        let svg = model.to_svg(model.target_size);
+        assert!(svg.contains("width=\"404\" height=\"377\""));
+    }
+
+    #[test]
+    fn should_prefer_user_stop_conditions_to_max_iter() {
+        let mut o = Options::default();
+        o.max_iter = 1;
+        o.seed = Some(1);
+        let mut simulation = Simulation::new(get_test_image(), o).unwrap();
+        simulation.step().unwrap();
+        // both the iteration limit and the target score are reached
+        simulation.options.target_score = Some(f32::MAX);
+        match simulation.step().unwrap() {
+            Event::Stopped(reason) => assert_eq!(reason, StopReason::TargetScore),
+            _ => panic!("expected the simulation to stop"),
+        }
+    }
+
+    #[test]
+    fn should_stop_iterating_after_an_error() {
+        let mut o = Options::default();
+        o.max_iter = 3;
+        o.seed = Some(1);
+        let mut simulation = Simulation::new(get_test_image(), o).unwrap();
+        // without mutations no generation has a winner
+        simulation.options.num_genes = 0;
+        let events: Vec<Result<Event>> = simulation.by_ref().collect();
+        assert_eq!(events.len(), 1);
+        assert!(events[0].is_err());
+        assert!(simulation.next().is_none());
+    }
+
+    #[test]
+    fn should_keep_drawing_the_stamp_of_a_resumed_model() {
+        let stamp = Arc::new(Stamp::new(
+            "M0 0 H10 V20 Z".to_owned(),
+            [Point { x: 0.0, y: 0.0 }, Point { x: 10.0, y: 20.0 }],
+        ));
+        let polygon = Polygon::generate_stamp(&mut thread_rng(), stamp.clone(), 64.0, 64.0, None);
+        let background = Rgba {
+            data: [0, 0, 0, 255],
+        };
+        let model = Model::new(64, 64, background, vec![polygon]);
+
+        let simulation =
+            Simulation::resume(get_test_image(), model.clone(), Options::default()).unwrap();
+        assert_eq!(simulation.shape, Shapes::Stamp);
+        assert_eq!(simulation.stamp, Some(stamp));
+
+        let mut o = Options::default();
+        o.shape = "triangle".to_owned();
+        let simulation = Simulation::resume(get_test_image(), model, o).unwrap();
+        assert_eq!(simulation.shape, Shapes::Triangle);
+        assert_eq!(simulation.stamp, None);
+    }
+
+    #[test]
+    fn should_repeat_runs_with_the_same_seed() {
+        let run = || {
+            let mut o = Options::default();
+            o.max_iter = 5;
+            o.num_genes = 8;
+            o.threads = 4;
+            o.seed = Some(7);
+            Simulation::new(get_test_image(), o)
+                .unwrap()
+                .finish()
+                .unwrap()
+        };
+        let (first, second) = (run(), run());
+        assert_eq!(first.scores, second.scores);
+        assert_eq!(first.polygons.len(), second.polygons.len());
+        for (a, b) in first.polygons.iter().zip(&second.polygons) {
+            assert_eq!(a.points, b.points);
+        }
+    }
+
+    #[test]
+    fn should_place_shapes_by_error() {
+        // black but for the top left corner, on a black background
+        let img = RgbaImage::from_fn(64, 64, |x, y| {
+            let v = if x < 16 && y < 16 { 255 } else { 0 };
+            Rgba {
+                data: [v, v, v, 255],
+            }
+        });
+        let mut o = Options::default();
+        o.max_iter = 2;
+        o.num_genes = 2;
+        o.seed = Some(1);
+        o.background = "#000000".to_owned();
+        o.placement = "error".to_owned();
+        let mut simulation = Simulation::new(Arc::new(DynamicImage::ImageRgba8(img)), o).unwrap();
+        assert!(simulation.error_map.is_none());
+        let current = simulation.result_gene.as_rgba_img().unwrap();
+        let limits = simulation.current_limits();
+        for &shape in [Shapes::Rectangle, Shapes::Triangle].iter() {
+            simulation.shape = shape;
+            for i in 0..20 {
+                let candidate = simulation.candidate(&current, &limits);
+                let sampled = candidate.center();
+                assert!(sampled.x < 16.0 && sampled.y < 16.0);
+                // scaled, rotated and constrained, still centered there
+                let mutated = simulation
+                    .result_gene
+                    .mutate(&mut thread_rng(), candidate, i);
+                let center = mutated.get_last_polygon().unwrap().center();
+                assert!((center.x - sampled.x).abs() < 1e-3, "{}", shape);
+                assert!((center.y - sampled.y).abs() < 1e-3, "{}", shape);
+            }
+        }
+        assert!(simulation.error_map.is_some());
+
+        simulation.step().unwrap();
+        let model = simulation.finish().unwrap();
+        assert_eq!(model.stop_reason, Some(StopReason::MaxIterations));
+    }
+
+    #[test]
+    fn should_shrink_shapes_by_schedule() {
+        let linear = SizeSchedule::Linear;
+        assert_eq!(linear.max_size(100.0, 10.0, 0.0), 100.0);
+        assert_eq!(linear.max_size(100.0, 10.0, 0.5), 55.0);
+        assert_eq!(linear.max_size(100.0, 10.0, 2.0), 10.0);
+        let exponential = SizeSchedule::Exponential;
+        assert!((exponential.max_size(100.0, 1.0, 0.5) - 10.0).abs() < 1e-4);
+        let step = SizeSchedule::Step;
+        assert_eq!(step.max_size(1000.0, 1.0, 0.2), 1000.0);
+        assert!((step.max_size(1000.0, 1.0, 0.3) - 100.0).abs() < 1e-3);
+        assert!((step.max_size(1000.0, 1.0, 1.0) - 1.0).abs() < 1e-4);
+
+        let mut o = Options::default();
+        o.max_iter = 4;
+        o.num_genes = 2;
+        o.seed = Some(1);
+        o.max_shape_size = Some("50%".to_owned());
+        o.min_shape_size = Some("4".to_owned());
+        o.size_schedule = Some("linear".to_owned());
+        let mut simulation = Simulation::new(get_test_image(), o).unwrap();
+        let (width, height) = simulation.target.dimensions();
+        let start = width.max(height) as f32 / 2.0;
+        let limits = simulation.current_limits();
+        assert_eq!(limits.max_size, Some(start));
+        assert_eq!(limits.min_size, Some(4.0));
+        // halfway through the generations, whether shapes were added or not
+        simulation.step().unwrap();
+        simulation.step().unwrap();
+        let limits = simulation.current_limits();
+        assert_eq!(limits.max_size, Some(linear.max_size(start, 4.0, 0.5)));
+        simulation.step().unwrap();
+        simulation.step().unwrap();
+        assert_eq!(simulation.current_limits().max_size, Some(4.0));
+        let model = simulation.finish().unwrap();
+        assert_eq!(model.stop_reason, Some(StopReason::MaxIterations));
+    }
+
+    #[test]
+    fn should_pick_background() {
+        let target = get_test_image();
+        let mean = get_average_color(target.clone());
+        assert_eq!(Background::Mean.color(target.clone()), mean);
+        assert_eq!(Background::Transparent.color(target.clone()).data[3], 0);
+        let color: Background = "#ff8000".parse().unwrap();
+        assert_eq!(color.color(target.clone()).data, [255, 128, 0, 255]);
+        assert_eq!(color.to_string(), "#ff8000");
+
+        // a flat backdrop with a small square picks the backdrop
+        let flat = RgbaImage::from_fn(32, 32, |x, y| {
+            if x < 8 && y < 8 {
+                Rgba {
+                    data: [0, 0, 0, 255],
+                }
+            } else {
+                Rgba {
+                    data: [240, 240, 240, 255],
+                }
+            }
+        });
+        let flat = Arc::new(DynamicImage::ImageRgba8(flat));
+        assert_eq!(
+            Background::Auto.color(flat.clone()).data,
+            [240, 240, 240, 255]
+        );
+        assert_eq!(Background::Dominant.color(flat).data, [240, 240, 240, 255]);
+
+        let mut o = Options::default();
+        o.max_iter = 1;
+        o.num_genes = 2;
+        o.background = "transparent".to_owned();
+        let model = Simulation::new(get_test_image(), o)
+            .unwrap()
+            .finish()
+            .unwrap();
+        assert_eq!(model.background.data[3], 0);
+        assert!(!model.to_svg(model.size()).contains("<rect"));
+    }
+
+    #[test]
+    fn should_place_shapes_over_visible_pixels() {
+        // the left half of the target is transparent
+        let img = RgbaImage::from_fn(64, 64, |x, _| Rgba {
+            data: [200, 100, 50, if x < 32 { 0 } else { 255 }],
+        });
+        let target = Arc::new(DynamicImage::ImageRgba8(img));
+        assert_eq!(Background::Auto.color(target.clone()).data[3], 0);
+
+        let mut o = Options::default();
+        o.max_iter = 1;
+        o.num_genes = 1;
+        o.seed = Some(3);
+        let mut simulation = Simulation::new(target, o).unwrap();
+        assert!(simulation.visibility.is_some());
+        for _ in 0..50 {
+            let visible = simulation.visible_weights.as_ref().unwrap();
+            assert!(visible.sample(&mut simulation.rng).x >= 32.0);
+        }
+        simulation.step().unwrap();
+        let model = simulation.finish().unwrap();
+        assert_eq!(model.background.data[3], 0);
+    }
+
+    #[test]
+    fn should_paint_with_palette() {
+        let img = RgbaImage::from_fn(64, 64, |x, y| Rgba {
+            data: [(x * 4) as u8, (y * 4) as u8, 128, 255],
+        });
+        let mut o = Options::default();
+        o.max_iter = 5;
+        o.num_genes = 4;
+        o.seed = Some(5);
+        o.palette_size = Some(3);
+        let target = Arc::new(DynamicImage::ImageRgba8(img));
+        let palette = Palette::from_target(&target, 3);
+        let simulation = Simulation::new(target, o).unwrap();
+        let mut generation = simulation.result_gene.clone();
+        let candidate = Polygon::new(&mut thread_rng(), Shapes::Rectangle, 64.0, 64.0);
+        generation = generation.mutate(&mut thread_rng(), candidate, 1);
+        let mut color = generation.get_last_polygon().unwrap().fill_color();
+        color.data[3] = 255;
+        assert!(palette.colors().contains(&color));
+        let model = simulation.finish().unwrap();
+        assert!(palette.colors().contains(&model.background));
+    }
+
+    #[test]
+    fn should_paint_in_mono() {
+        let mut o = Options::default();
+        o.max_iter = 3;
+        o.num_genes = 2;
+        o.seed = Some(2);
+        o.color_mode = "mono".to_owned();
+        o.inks = Some("#102080".to_owned());
+        let simulation = Simulation::new(get_test_image(), o).unwrap();
+        assert_eq!(simulation.result_gene.to_model().background.data, [255; 4]);
+        // the target is projected on the line from the paper to the ink
+        let p = simulation.target.get_pixel(0, 0);
+        assert!(p.data[0] < p.data[1] && p.data[1] < p.data[2]);
+
+        let candidate = Polygon::new(&mut thread_rng(), Shapes::Rectangle, 64.0, 64.0);
+        let mutated = simulation
+            .result_gene
+            .mutate(&mut thread_rng(), candidate, 1);
+        let fill = mutated.get_last_polygon().unwrap().fill_color();
+        assert_eq!(&fill.data[..3], &[16, 32, 128]);
+        assert!(fill.data[3] > 0 && fill.data[3] < 255);
+        simulation.finish().unwrap();
+    }
+
+    #[test]
+    fn should_stop_when_cancelled() {
+        let mut o = Options::default();
+        o.max_iter = 100;
+        o.num_genes = 2;
+        let mut simulation = Simulation::new(get_test_image(), o).unwrap();
+        let token = CancelToken::new();
+        simulation.set_cancel_token(token.clone());
+
+        let (sender, receiver) = ::std::sync::mpsc::channel();
+        simulation.set_observer(move |event, _| sender.send(event.clone()).unwrap());
+
+        simulation.step().unwrap();
+        token.cancel();
+        let model = simulation.finish().unwrap();
+        assert_eq!(model.stop_reason, Some(StopReason::Cancelled));
+
+        let events: Vec<Event> = receiver.try_iter().collect();
+        assert_eq!(events.len(), 2);
+        match events[1] {
+            Event::Stopped(reason) => assert_eq!(reason, StopReason::Cancelled),
+            _ => panic!("expected the simulation to stop"),
+        }
+    }
 }
@@ -0,0 +1,269 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use error::{Error, Result};
+use model::rasterize;
+use shape::Point;
+
+/// id of the path definition `<use>` elements of stamps refer to
+pub const SVG_ID: &str = "stamp";
+/// longest side of the raster the covered area of a stamp is measured on
+const COVERAGE_SIZE: f32 = 256.0;
+
+/**
+ * A single svg path used as a shape instead of rectangles or triangles.
+ *
+ * The path is the `d` attribute of the only `<path>` of the file, framed by
+ * the `viewBox` (or `width` and `height`) of the `<svg>` element; transforms
+ * and styles in the file are ignored. The path is rasterized once to find the
+ * area it covers, polygons of stamps are that area moved, rotated, scaled and
+ * colored, and the path is drawn into them.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stamp {
+    /// the `d` attribute of the path, whitespace collapsed to single spaces
+    path: String,
+    /// the area covered by the path, in its own coordinates
+    bounds: [Point; 2],
+}
+
+impl Stamp {
+    pub fn new(path: String, bounds: [Point; 2]) -> Stamp {
+        Stamp {
+            path: path,
+            bounds: bounds,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Stamp> {
+        let mut svg = String::new();
+        File::open(path)?.read_to_string(&mut svg)?;
+        Stamp::parse(&svg).map_err(|e| Error::InvalidStamp(format!("{}: {}", path.display(), e)))
+    }
+
+    fn parse(svg: &str) -> ::std::result::Result<Stamp, String> {
+        let (path, frame) = path_and_frame(svg)?;
+        let bounds = coverage(&path, &frame)?;
+        Ok(Stamp::new(path, bounds))
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn bounds(&self) -> &[Point; 2] {
+        &self.bounds
+    }
+
+    /// the `<defs>` element of the path, without a fill so `<use>` elements
+    /// color it
+    pub fn svg_definition(&self) -> String {
+        format!("<defs><path id=\"{}\" d=\"{}\"/></defs>", SVG_ID, self.path)
+    }
+
+    /// height of the covered area divided by its width
+    pub fn aspect_ratio(&self) -> f32 {
+        (self.bounds[1].y - self.bounds[0].y) / (self.bounds[1].x - self.bounds[0].x)
+    }
+
+    /**
+     * @brief      the svg transform that maps the covered area onto a
+     *             rectangle
+     *
+     * @param      corners  the rectangle, clockwise from the top left corner
+     *                      of the stamp
+     */
+    pub fn transform(&self, corners: &[Point]) -> String {
+        let [a, b, c, d, e, f] = self.matrix(corners);
+        format!("matrix({} {} {} {} {} {})", a, b, c, d, e, f)
+    }
+
+    /// how much the transform onto `corners` scales lengths of the path
+    pub fn scale(&self, corners: &[Point]) -> f32 {
+        let [a, b, c, d, _, _] = self.matrix(corners);
+        (a * d - b * c).abs().sqrt()
+    }
+
+    fn matrix(&self, corners: &[Point]) -> [f32; 6] {
+        let [ref min, ref max] = self.bounds;
+        let (width, height) = (max.x - min.x, max.y - min.y);
+        let (p0, p1, p3) = (&corners[0], &corners[1], &corners[3]);
+        let (a, b) = ((p1.x - p0.x) / width, (p1.y - p0.y) / width);
+        let (c, d) = ((p3.x - p0.x) / height, (p3.y - p0.y) / height);
+        [
+            a,
+            b,
+            c,
+            d,
+            p0.x - a * min.x - c * min.y,
+            p0.y - b * min.x - d * min.y,
+        ]
+    }
+}
+
+/// the path data and the `[x, y, width, height]` frame of a stamp file
+fn path_and_frame(svg: &str) -> ::std::result::Result<(String, [f32; 4]), String> {
+    let paths = tags(svg, "path");
+    if paths.len() != 1 {
+        return Err(format!("expected a single path, found {}", paths.len()));
+    }
+    let path = attribute(paths[0], "d")
+        .map(|d| d.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|d| !d.is_empty())
+        .ok_or_else(|| "the path has no `d` attribute".to_owned())?;
+
+    let root = tags(svg, "svg")
+        .into_iter()
+        .next()
+        .ok_or_else(|| "no <svg> element found".to_owned())?;
+    let number = |value: &str| value.trim_end_matches("px").parse::<f32>().ok();
+    let frame = match attribute(root, "viewBox") {
+        Some(view_box) => {
+            let values: Vec<f32> = view_box
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|v| !v.is_empty())
+                .filter_map(number)
+                .collect();
+            if values.len() != 4 {
+                return Err(format!("invalid viewBox `{}`", view_box));
+            }
+            [values[0], values[1], values[2], values[3]]
+        }
+        None => match (
+            attribute(root, "width").and_then(number),
+            attribute(root, "height").and_then(number),
+        ) {
+            (Some(width), Some(height)) => [0.0, 0.0, width, height],
+            _ => return Err("the <svg> element needs a viewBox or a width and height".to_owned()),
+        },
+    };
+    if !(frame[2] > 0.0 && frame[3] > 0.0) {
+        return Err("the frame of the path is empty".to_owned());
+    }
+    Ok((path, frame))
+}
+
+/// the area of `frame` the rasterized path covers, in path coordinates
+fn coverage(path: &str, frame: &[f32; 4]) -> ::std::result::Result<[Point; 2], String> {
+    let scale = COVERAGE_SIZE / frame[2].max(frame[3]);
+    let svg = format!(
+        "<svg width=\"{}\" height=\"{}\" viewBox=\"{} {} {} {}\" xmlns=\"http://www.w3.org/2000/svg\"><path d=\"{}\"/></svg>",
+        frame[2] * scale,
+        frame[3] * scale,
+        frame[0],
+        frame[1],
+        frame[2],
+        frame[3],
+        path
+    );
+    let raster = rasterize(&svg).map_err(|e| e.to_string())?;
+    let (width, height) = raster.dimensions();
+    let mut covered: Option<[u32; 4]> = None;
+    for (x, y, pixel) in raster.enumerate_pixels() {
+        if pixel.data[3] == 0 {
+            continue;
+        }
+        covered = Some(match covered {
+            Some([x0, y0, x1, y1]) => [x0.min(x), y0.min(y), x1.max(x), y1.max(y)],
+            None => [x, y, x, y],
+        });
+    }
+    let [x0, y0, x1, y1] = covered.ok_or_else(|| "the path covers no pixels".to_owned())?;
+    let to_path = |x: u32, y: u32| Point {
+        x: frame[0] + x as f32 * frame[2] / width as f32,
+        y: frame[1] + y as f32 * frame[3] / height as f32,
+    };
+    Ok([to_path(x0, y0), to_path(x1 + 1, y1 + 1)])
+}
+
+/// the attributes of every `<name ...>` element
+fn tags<'a>(svg: &'a str, name: &str) -> Vec<&'a str> {
+    let open = format!("<{}", name);
+    svg.match_indices(open.as_str())
+        .filter_map(|(i, _)| {
+            let rest = &svg[i + open.len()..];
+            let tag = &rest[..rest.find('>')?];
+            let named = tag.is_empty() || tag.starts_with(|c: char| c.is_whitespace() || c == '/');
+            if named {
+                Some(tag)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// the value of the attribute `name` of a tag, in single or double quotes
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = tag;
+    while let Some(i) = rest.find(name) {
+        let separated = rest[..i].ends_with(char::is_whitespace);
+        rest = &rest[i + name.len()..];
+        let value = rest.trim_start();
+        if !separated || !value.starts_with('=') {
+            continue;
+        }
+        let value = value[1..].trim_start();
+        let quote = value.chars().next().filter(|&c| c == '"' || c == '\'')?;
+        let value = &value[1..];
+        return value.find(quote).map(|end| &value[..end]);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEART: &str = r#"<?xml version="1.0"?>
+<svg xmlns="http://www.w3.org/2000/svg" width="24px" height="24px" viewBox="0 0 24 24">
+  <path fill="red" d="M12 21 L3 12
+    C0 8 5 2 12 7 C19 2 24 8 21 12 Z"/>
+</svg>"#;
+
+    #[test]
+    fn should_read_path_and_frame() {
+        let (path, frame) = path_and_frame(HEART).unwrap();
+        assert_eq!(path, "M12 21 L3 12 C0 8 5 2 12 7 C19 2 24 8 21 12 Z");
+        assert_eq!(frame, [0.0, 0.0, 24.0, 24.0]);
+
+        let sized = "<svg width='10' height=\"20px\"><path d='M0 0 H10 V20 Z'/></svg>";
+        assert_eq!(path_and_frame(sized).unwrap().1, [0.0, 0.0, 10.0, 20.0]);
+    }
+
+    #[test]
+    fn should_reject_invalid_stamps() {
+        let two = "<svg viewBox=\"0 0 1 1\"><path d=\"M0 0\"/><path d=\"M1 1\"/></svg>";
+        assert!(path_and_frame(two).is_err());
+        assert!(path_and_frame("<svg viewBox=\"0 0 1 1\"><pathway/></svg>").is_err());
+        assert!(path_and_frame("<svg viewBox=\"0 0 1 1\"><path id=\"d\"/></svg>").is_err());
+        assert!(path_and_frame("<svg><path d=\"M0 0 L1 1\"/></svg>").is_err());
+        assert!(path_and_frame("<svg viewBox=\"0 0 0 1\"><path d=\"M0 0 L1 1\"/></svg>").is_err());
+    }
+
+    #[test]
+    fn should_map_onto_corners() {
+        let stamp = Stamp::new(
+            "M0 0 H10 V20 Z".to_owned(),
+            [Point { x: 0.0, y: 0.0 }, Point { x: 10.0, y: 20.0 }],
+        );
+        assert_eq!(stamp.aspect_ratio(), 2.0);
+        let corners = [
+            Point { x: 5.0, y: 5.0 },
+            Point { x: 15.0, y: 5.0 },
+            Point { x: 15.0, y: 25.0 },
+            Point { x: 5.0, y: 25.0 },
+        ];
+        assert_eq!(stamp.transform(&corners), "matrix(1 0 0 1 5 5)");
+        // turned clockwise by 90 degrees and half the size
+        let corners = [
+            Point { x: 10.0, y: 0.0 },
+            Point { x: 10.0, y: 5.0 },
+            Point { x: 0.0, y: 5.0 },
+            Point { x: 0.0, y: 0.0 },
+        ];
+        assert_eq!(stamp.transform(&corners), "matrix(0 0.5 -0.5 0 10 0)");
+        assert_eq!(stamp.scale(&corners), 0.5);
+    }
+}
@@ -0,0 +1,98 @@
+use std::path::{Path, PathBuf};
+
+use error::{Error, Result};
+use image::{DynamicImage, RgbaImage};
+use image_utils::{load_image, load_image_from_memory};
+
+/**
+ * The image a simulation tries to mimic. It can be a file, an image that is
+ * already decoded or an encoded buffer (png, jpeg, ...) in memory.
+ */
+pub enum Target<'a> {
+    Path(&'a Path),
+    Image(DynamicImage),
+    Bytes(&'a [u8]),
+}
+
+impl<'a> Target<'a> {
+    /// decodes the target if necessary
+    pub fn load(self) -> Result<DynamicImage> {
+        match self {
+            Target::Path(path) => load_image(path).map_err(|e| Error::Load(path.to_path_buf(), e)),
+            Target::Image(img) => Ok(img),
+            Target::Bytes(bytes) => Ok(load_image_from_memory(bytes)?),
+        }
+    }
+}
+
+impl<'a> From<&'a Path> for Target<'a> {
+    fn from(path: &'a Path) -> Target<'a> {
+        Target::Path(path)
+    }
+}
+
+impl<'a> From<&'a PathBuf> for Target<'a> {
+    fn from(path: &'a PathBuf) -> Target<'a> {
+        Target::Path(path.as_path())
+    }
+}
+
+impl<'a> From<DynamicImage> for Target<'a> {
+    fn from(img: DynamicImage) -> Target<'a> {
+        Target::Image(img)
+    }
+}
+
+impl<'a> From<RgbaImage> for Target<'a> {
+    fn from(img: RgbaImage) -> Target<'a> {
+        Target::Image(DynamicImage::ImageRgba8(img))
+    }
+}
+
+impl<'a> From<&'a [u8]> for Target<'a> {
+    fn from(bytes: &'a [u8]) -> Target<'a> {
+        Target::Bytes(bytes)
+    }
+}
+
+impl<'a> From<&'a Vec<u8>> for Target<'a> {
+    fn from(bytes: &'a Vec<u8>) -> Target<'a> {
+        Target::Bytes(bytes.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImage, Rgba};
+    use std::fs::File;
+    use std::io::Read;
+
+    #[test]
+    fn should_load_encoded_bytes() {
+        let root_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let mut bytes = Vec::new();
+        File::open(root_dir.join("four-colors.png"))
+            .unwrap()
+            .read_to_end(&mut bytes)
+            .unwrap();
+        let from_bytes = Target::from(&bytes).load().unwrap();
+        let from_path = Target::from(&root_dir.join("four-colors.png"))
+            .load()
+            .unwrap();
+        assert_eq!(from_bytes.dimensions(), from_path.dimensions());
+        assert_eq!(from_bytes.raw_pixels(), from_path.raw_pixels());
+    }
+
+    #[test]
+    fn should_reject_garbage_bytes() {
+        assert!(Target::from(&b"not an image"[..]).load().is_err());
+    }
+
+    #[test]
+    fn should_wrap_rgba_images() {
+        let img = RgbaImage::from_pixel(3, 2, Rgba { data: [1, 2, 3, 4] });
+        let target = Target::from(img).load().unwrap();
+        assert_eq!(target.dimensions(), (3, 2));
+    }
+}
@@ -1,5 +1,5 @@
 use rand::distributions::{IndependentSample, Range};
-use rand::thread_rng;
+use rand::Rng;
 use shape::{Point, Shape};
 
 #[derive(Debug)]
@@ -8,34 +8,40 @@ pub struct Triangle {
 }
 
 impl Shape for Triangle {
-    fn new(range_x: &f32, range_y: &f32) -> Vec<Point> {
-        let mut rng = thread_rng();
+    fn new<R: Rng>(rng: &mut R, range_x: &f32, range_y: &f32, max_size: Option<f32>) -> Vec<Point> {
         let n_tiles = 4;
         let x_index_generator = Range::new(0, n_tiles);
         let y_index_generator = Range::new(0, n_tiles);
         let (tile_x, tile_y) = (
-            x_index_generator.ind_sample(&mut rng) as f32,
-            y_index_generator.ind_sample(&mut rng) as f32,
+            x_index_generator.ind_sample(rng) as f32,
+            y_index_generator.ind_sample(rng) as f32,
         );
         let tile_size_x = range_x / n_tiles as f32;
         let tile_size_y = range_y / n_tiles as f32;
+        // no edge of a triangle inside a box is longer than its diagonal
+        let box_size = max_size.map_or(f32::INFINITY, |s| s / 2f32.sqrt());
+        let (box_x, box_y) = (tile_size_x.min(box_size), tile_size_y.min(box_size));
+        let (min_x, min_y) = (
+            tile_size_x * tile_x + offset(rng, tile_size_x - box_x),
+            tile_size_y * tile_y + offset(rng, tile_size_y - box_y),
+        );
         let (x_coord_generator, y_coord_generator) = (
-            Range::new(tile_size_x * tile_x, tile_size_x * (tile_x + 1.0)),
-            Range::new(tile_size_y * tile_y, tile_size_y * (tile_y + 1.0)),
+            Range::new(min_x, min_x + box_x),
+            Range::new(min_y, min_y + box_y),
         );
         // let x_generator = Range::new(img_tile * tile_size_x, (img_tile + 1) * tile_size_x);
         // let y_generator = Range::new(img_tile * tile_size_y, (img_tile + 1) * tile_size_y);
         let p1 = Point {
-            x: x_coord_generator.ind_sample(&mut rng),
-            y: y_coord_generator.ind_sample(&mut rng),
+            x: x_coord_generator.ind_sample(rng),
+            y: y_coord_generator.ind_sample(rng),
         };
         let p2 = Point {
-            x: x_coord_generator.ind_sample(&mut rng),
-            y: y_coord_generator.ind_sample(&mut rng),
+            x: x_coord_generator.ind_sample(rng),
+            y: y_coord_generator.ind_sample(rng),
         };
         let p3 = Point {
-            x: x_coord_generator.ind_sample(&mut rng),
-            y: y_coord_generator.ind_sample(&mut rng),
+            x: x_coord_generator.ind_sample(rng),
+            y: y_coord_generator.ind_sample(rng),
         };
         // let p3 = Point {
         //     x: (p1.x + p2.x) / 2;
@@ -47,25 +53,76 @@ impl Shape for Triangle {
         points
     }
 
-    fn center(_points: &Vec<Point>) -> Point {
-        unimplemented!();
+    /// the centroid, the mean of the vertices
+    fn center(points: &Vec<Point>) -> Point {
+        let n = points.len() as f32;
+        Point {
+            x: points.iter().map(|p| p.x).sum::<f32>() / n,
+            y: points.iter().map(|p| p.y).sum::<f32>() / n,
+        }
     }
 
-    fn scale(_points: &mut Vec<Point>, _scale_x: &f32, _scale_y: &f32) {
-        unimplemented!();
+    /// scales the triangle about its centroid, which stays in place
+    fn scale(points: &mut Vec<Point>, scale_x: &f32, scale_y: &f32) {
+        let center = Triangle::center(points);
+        for point in points.iter_mut() {
+            point.x = center.x + (point.x - center.x) * scale_x;
+            point.y = center.y + (point.y - center.y) * scale_y;
+        }
+    }
+}
+
+/// random offset in `[0, slack)`, 0 when there is no room
+fn offset<R: Rng>(rng: &mut R, slack: f32) -> f32 {
+    if slack > 0.0 {
+        Range::new(0.0, slack).ind_sample(rng)
+    } else {
+        0.0
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::thread_rng;
 
     #[test]
     fn should_be_within_range() {
-        let points = Triangle::new(&512.0, &512.0);
+        let points = Triangle::new(&mut thread_rng(), &512.0, &512.0, None);
         for point in &points {
             assert!(point.x < 512.0);
             assert!(point.y < 512.0);
         }
     }
+
+    #[test]
+    fn should_respect_max_size() {
+        for _ in 0..100 {
+            let points = Triangle::new(&mut thread_rng(), &512.0, &512.0, Some(10.0));
+            for i in 0..3 {
+                let (a, b) = (&points[i], &points[(i + 1) % 3]);
+                assert!((a.x - b.x).hypot(a.y - b.y) <= 10.0 + 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn should_scale_about_the_center() {
+        let mut points = vec![
+            Point { x: 0.0, y: 0.0 },
+            Point { x: 30.0, y: 0.0 },
+            Point { x: 0.0, y: 60.0 },
+        ];
+        assert_eq!(Triangle::center(&points), Point { x: 10.0, y: 20.0 });
+        Triangle::scale(&mut points, &2.0, &0.5);
+        assert_eq!(
+            points,
+            vec![
+                Point { x: -10.0, y: 10.0 },
+                Point { x: 50.0, y: 10.0 },
+                Point { x: -10.0, y: 40.0 },
+            ]
+        );
+        assert_eq!(Triangle::center(&points), Point { x: 10.0, y: 20.0 });
+    }
 }
@@ -0,0 +1,406 @@
+use std::cmp::Ordering;
+use std::f64::consts::PI;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use error::{Error, Result};
+use image::{self, DynamicImage, FilterType, GenericImage, GrayImage, Luma};
+use rand::distributions::{IndependentSample, Range};
+use rand::Rng;
+use shape::Point;
+
+/// side of the image the saliency map is computed on
+const SALIENCY_SIZE: u32 = 64;
+/// weight of the least important pixels of an automatic map, flat areas
+/// still need to be painted
+const MIN_AUTO_WEIGHT: f32 = 0.2;
+
+/// weight maps that are computed from the target
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AutoWeights {
+    /// Sobel gradient magnitude
+    Edges,
+    /// spectral residual saliency
+    Saliency,
+}
+
+impl FromStr for AutoWeights {
+    type Err = String;
+
+    fn from_str(s: &str) -> ::std::result::Result<AutoWeights, String> {
+        match s {
+            "edges" => Ok(AutoWeights::Edges),
+            "saliency" => Ok(AutoWeights::Saliency),
+            _ => Err(format!("unknown weight map: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for AutoWeights {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AutoWeights::Edges => write!(f, "edges"),
+            AutoWeights::Saliency => write!(f, "saliency"),
+        }
+    }
+}
+
+/**
+ * Per-pixel importance of the target, between 0.0 and 1.0. It scales the
+ * error of every pixel in the metrics and biases where new shapes are
+ * placed, so bright areas of the mask are reproduced with more fidelity.
+ */
+#[derive(Debug, Clone)]
+pub struct Weights {
+    width: u32,
+    height: u32,
+    data: Vec<f32>,
+    /// running sum of `data`, used to sample positions
+    cumulative: Vec<f64>,
+}
+
+impl Weights {
+    /**
+     * @brief      loads a grayscale mask and resizes it to `width` x
+     *             `height`, an all black mask would weight every error to
+     *             zero and is rejected
+     */
+    pub fn load(path: &Path, width: u32, height: u32) -> Result<Weights> {
+        let mask = image::open(path).map_err(|e| Error::Load(path.to_path_buf(), e))?;
+        let weights = Weights::from_image(&mask, width, height);
+        if weights.total() <= 0.0 {
+            return Err(Error::InvalidOptions(format!(
+                "the weight mask {} is all black",
+                path.display()
+            )));
+        }
+        Ok(weights)
+    }
+
+    pub fn from_image(mask: &DynamicImage, width: u32, height: u32) -> Weights {
+        let mask = if mask.dimensions() != (width, height) {
+            mask.resize_exact(width, height, FilterType::Triangle)
+        } else {
+            mask.clone()
+        };
+        let data = mask
+            .to_luma()
+            .pixels()
+            .map(|p| p.data[0] as f32 / 255.0)
+            .collect();
+        Weights::from_data(width, height, data)
+    }
+
+    /// computes a weight map of the kind `auto` from the target
+    pub fn auto(auto: AutoWeights, target: &DynamicImage) -> Weights {
+        let (width, height) = target.dimensions();
+        let gray = target.to_luma();
+        let map = match auto {
+            AutoWeights::Edges => box_blur(&sobel(&gray), width, height),
+            AutoWeights::Saliency => {
+                let small = DynamicImage::ImageLuma8(gray)
+                    .resize(SALIENCY_SIZE, SALIENCY_SIZE, FilterType::Triangle)
+                    .to_luma();
+                let (w, h) = small.dimensions();
+                let map = spectral_residual(&small);
+                return Weights::from_map(&box_blur(&box_blur(&map, w, h), w, h), w, h)
+                    .resized(width, height);
+            }
+        };
+        Weights::from_map(&map, width, height)
+    }
+
+    /// normalizes `map` to weights between `MIN_AUTO_WEIGHT` and 1.0
+    fn from_map(map: &[f64], width: u32, height: u32) -> Weights {
+        let max = map.iter().cloned().fold(0.0, f64::max);
+        let data = map
+            .iter()
+            .map(|v| {
+                let v = if max > 0.0 { (v / max) as f32 } else { 0.0 };
+                MIN_AUTO_WEIGHT + (1.0 - MIN_AUTO_WEIGHT) * v
+            })
+            .collect();
+        Weights::from_data(width, height, data)
+    }
+
+    fn resized(&self, width: u32, height: u32) -> Weights {
+        let mask = GrayImage::from_fn(self.width, self.height, |x, y| Luma {
+            data: [(self.get(x, y) * 255.0).round() as u8],
+        });
+        Weights::from_image(&DynamicImage::ImageLuma8(mask), width, height)
+    }
+
+    /// `data` holds one weight per pixel, row by row
+    pub fn from_data(width: u32, height: u32, data: Vec<f32>) -> Weights {
+        let mut sum = 0.0;
+        let cumulative = data
+            .iter()
+            .map(|w| {
+                sum += *w as f64;
+                sum
+            })
+            .collect();
+        Weights {
+            width: width,
+            height: height,
+            data: data,
+            cumulative: cumulative,
+        }
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> f32 {
+        self.data[(y * self.width + x) as usize]
+    }
+
+    /// sum of all weights
+    pub fn total(&self) -> f64 {
+        self.cumulative.last().cloned().unwrap_or(0.0)
+    }
+
+    /**
+     * @brief      picks a position with a probability proportional to its
+     *             weight, uniformly when every weight is zero
+     */
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> Point {
+        let jitter = Range::new(0.0, 1.0);
+        let total = self.total();
+        let index = if total > 0.0 {
+            let needle = Range::new(0.0, total).ind_sample(rng);
+            // first pixel whose running sum exceeds the needle
+            let search = self.cumulative.binary_search_by(|c| {
+                if *c <= needle {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                }
+            });
+            match search {
+                Ok(i) | Err(i) => i.min(self.data.len() - 1),
+            }
+        } else {
+            Range::new(0, self.data.len()).ind_sample(rng)
+        };
+        let index = index as u32;
+        Point {
+            x: (index % self.width) as f32 + jitter.ind_sample(rng),
+            y: (index / self.width) as f32 + jitter.ind_sample(rng),
+        }
+    }
+}
+
+/// Sobel gradient magnitude, the border is extended
+fn sobel(gray: &GrayImage) -> Vec<f64> {
+    let (width, height) = gray.dimensions();
+    let at = |x: i64, y: i64| {
+        let x = x.max(0).min(width as i64 - 1) as u32;
+        let y = y.max(0).min(height as i64 - 1) as u32;
+        gray.get_pixel(x, y).data[0] as f64
+    };
+    let mut map = Vec::with_capacity((width * height) as usize);
+    for y in 0..height as i64 {
+        for x in 0..width as i64 {
+            let gx = at(x + 1, y - 1) + 2.0 * at(x + 1, y) + at(x + 1, y + 1)
+                - at(x - 1, y - 1)
+                - 2.0 * at(x - 1, y)
+                - at(x - 1, y + 1);
+            let gy = at(x - 1, y + 1) + 2.0 * at(x, y + 1) + at(x + 1, y + 1)
+                - at(x - 1, y - 1)
+                - 2.0 * at(x, y - 1)
+                - at(x + 1, y - 1);
+            map.push((gx * gx + gy * gy).sqrt());
+        }
+    }
+    map
+}
+
+/// 3x3 mean filter, the border is extended
+fn box_blur(map: &[f64], width: u32, height: u32) -> Vec<f64> {
+    let (width, height) = (width as i64, height as i64);
+    let mut blurred = Vec::with_capacity(map.len());
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = 0.0;
+            for dy in -1..2 {
+                for dx in -1..2 {
+                    let sx = (x + dx).max(0).min(width - 1);
+                    let sy = (y + dy).max(0).min(height - 1);
+                    sum += map[(sy * width + sx) as usize];
+                }
+            }
+            blurred.push(sum / 9.0);
+        }
+    }
+    blurred
+}
+
+/**
+ * @brief      spectral residual saliency (Hou and Zhang, 2007): the log
+ *             amplitude spectrum minus its local average keeps what is
+ *             unexpected in the image, transformed back with the original
+ *             phase it highlights the salient regions
+ */
+fn spectral_residual(gray: &GrayImage) -> Vec<f64> {
+    let (width, height) = gray.dimensions();
+    let mut spectrum: Vec<(f64, f64)> = gray.pixels().map(|p| (p.data[0] as f64, 0.0)).collect();
+    dft2(&mut spectrum, width as usize, height as usize, false);
+
+    let log_amplitude: Vec<f64> = spectrum
+        .iter()
+        .map(|&(re, im)| (re * re + im * im).sqrt().ln_1p())
+        .collect();
+    let average = box_blur(&log_amplitude, width, height);
+    for (i, c) in spectrum.iter_mut().enumerate() {
+        let phase = c.1.atan2(c.0);
+        let residual = (log_amplitude[i] - average[i]).exp();
+        *c = (residual * phase.cos(), residual * phase.sin());
+    }
+
+    dft2(&mut spectrum, width as usize, height as usize, true);
+    spectrum.iter().map(|&(re, im)| re * re + im * im).collect()
+}
+
+/// separable 2d discrete fourier transform, only meant for small images
+fn dft2(data: &mut [(f64, f64)], width: usize, height: usize, inverse: bool) {
+    for y in 0..height {
+        let row: Vec<(f64, f64)> = data[y * width..(y + 1) * width].to_vec();
+        let row = dft(&row, inverse);
+        data[y * width..(y + 1) * width].copy_from_slice(&row);
+    }
+    for x in 0..width {
+        let column: Vec<(f64, f64)> = (0..height).map(|y| data[y * width + x]).collect();
+        for (y, c) in dft(&column, inverse).into_iter().enumerate() {
+            data[y * width + x] = c;
+        }
+    }
+}
+
+fn dft(input: &[(f64, f64)], inverse: bool) -> Vec<(f64, f64)> {
+    let n = input.len();
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let norm = if inverse { 1.0 / n as f64 } else { 1.0 };
+    (0..n)
+        .map(|k| {
+            let (mut re, mut im) = (0.0, 0.0);
+            for (t, &(a, b)) in input.iter().enumerate() {
+                let angle = sign * 2.0 * PI * ((k * t) % n) as f64 / n as f64;
+                let (sin, cos) = angle.sin_cos();
+                re += a * cos - b * sin;
+                im += a * sin + b * cos;
+            }
+            (re * norm, im * norm)
+        })
+        .collect()
+}
+
+/// weight of a pixel, every pixel counts fully without weights
+pub fn weight_at(weights: Option<&Weights>, x: u32, y: u32) -> f64 {
+    weights.map_or(1.0, |w| w.get(x, y) as f64)
+}
+
+/// sum of the weights of a `width` x `height` image
+pub fn total_weight(weights: Option<&Weights>, width: u32, height: u32) -> f64 {
+    weights.map_or(width as f64 * height as f64, |w| w.total())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Luma, Rgba, RgbaImage};
+    use rand::thread_rng;
+    use std::env;
+
+    #[test]
+    fn should_resize_mask_to_working_size() {
+        let mask = DynamicImage::ImageLuma8(image::ImageBuffer::from_pixel(
+            40,
+            20,
+            Luma { data: [255u8] },
+        ));
+        let weights = Weights::from_image(&mask, 20, 10);
+        assert_eq!(weights.dimensions(), (20, 10));
+        assert_eq!(weights.get(19, 9), 1.0);
+        assert_eq!(weights.total(), 200.0);
+    }
+
+    #[test]
+    fn should_reject_black_mask() {
+        let path = env::temp_dir().join("geoshaper-black-mask.png");
+        GrayImage::from_pixel(8, 8, Luma { data: [0u8] })
+            .save(&path)
+            .unwrap();
+        match Weights::load(&path, 8, 8) {
+            Err(Error::InvalidOptions(_)) => {}
+            other => panic!("expected invalid options, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_sample_weighted_pixels() {
+        // only the bottom right quarter matters
+        let mask = DynamicImage::ImageRgba8(RgbaImage::from_fn(16, 16, |x, y| {
+            let v = if x >= 8 && y >= 8 { 255 } else { 0 };
+            Rgba {
+                data: [v, v, v, 255],
+            }
+        }));
+        let weights = Weights::from_image(&mask, 16, 16);
+        let mut rng = thread_rng();
+        for _ in 0..100 {
+            let p = weights.sample(&mut rng);
+            assert!(p.x >= 8.0 && p.x < 16.0);
+            assert!(p.y >= 8.0 && p.y < 16.0);
+        }
+    }
+
+    #[test]
+    fn should_parse_auto_weights() {
+        assert_eq!("edges".parse(), Ok(AutoWeights::Edges));
+        assert_eq!("saliency".parse(), Ok(AutoWeights::Saliency));
+        assert!("faces".parse::<AutoWeights>().is_err());
+    }
+
+    #[test]
+    fn should_weight_edges() {
+        // left half black, right half white
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(32, 16, |x, _| {
+            let v = if x < 16 { 0 } else { 255 };
+            Rgba {
+                data: [v, v, v, 255],
+            }
+        }));
+        let weights = Weights::auto(AutoWeights::Edges, &img);
+        assert_eq!(weights.dimensions(), (32, 16));
+        assert_eq!(weights.get(2, 8), MIN_AUTO_WEIGHT);
+        assert!(weights.get(16, 8) > 0.9);
+    }
+
+    #[test]
+    fn should_find_salient_spot() {
+        // a single bright square on a gray background
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 64, |x, y| {
+            let v = if (40..48).contains(&x) && (8..16).contains(&y) {
+                255
+            } else {
+                128
+            };
+            Rgba {
+                data: [v, v, v, 255],
+            }
+        }));
+        let weights = Weights::auto(AutoWeights::Saliency, &img);
+        assert_eq!(weights.dimensions(), (64, 64));
+        assert!(weights.get(44, 12) > weights.get(12, 48));
+    }
+
+    #[test]
+    fn should_sample_uniformly_without_weight() {
+        let weights = Weights::from_data(4, 4, vec![0.0; 16]);
+        let p = weights.sample(&mut thread_rng());
+        assert!(p.x >= 0.0 && p.x < 4.0 && p.y >= 0.0 && p.y < 4.0);
+    }
+}
@@ -2,7 +2,6 @@ extern crate geoshaper;
 
 extern crate env_logger;
 
-use std::fs::remove_file;
 use std::path::Path;
 
 #[test]
@@ -18,7 +17,12 @@ fn smoke() {
     o.max_iter = 10;
     o.render_debug_rasters = false;
 
-    assert_eq!(geoshaper::run(&img_path, Some(o)).is_ok(), true);
-    assert_eq!(result_path.exists(), true);
-    assert_eq!(remove_file(result_path).is_ok(), true);
+    let model = geoshaper::run(&img_path, Some(o)).unwrap();
+    // the library does not write any files
+    assert!(!result_path.exists());
+    assert_eq!(model.scores.len(), model.polygons.len() + 1);
+    assert!(model.stop_reason.is_some());
+
+    let img = model.to_rgba_image(model.size()).unwrap();
+    assert_eq!(img.dimensions(), (model.width, model.height));
 }